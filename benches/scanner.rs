@@ -0,0 +1,90 @@
+//! Compare the motif scanners on synthetic genomes. Run with
+//! `cargo bench --bench scanner`.
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+use tidk::utils::{find_exact_motifs, reverse_complement, BitScanner};
+
+/// The lengths of the synthetic genomes.
+const GENOME_LENGTHS: [usize; 2] = [1_000_000, 50_000_000];
+/// The telomeric repeats to search for.
+const MOTIFS: [&str; 3] = ["TTAGG", "TTAGGG", "TTTAGGG"];
+/// The number of copies of the telomeric repeat at either end.
+const ARRAY_COPIES: usize = 2000;
+/// Each scanner is run this many times, and the fastest kept.
+const ROUNDS: usize = 5;
+
+/// A random genome, with a telomeric array at either end, the right
+/// way round for each end, and some gaps.
+fn genome(length: usize, motif: &str, rng: &mut StdRng) -> Vec<u8> {
+    let mut genome: Vec<u8> = (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+    for _ in 0..length / 100_000 {
+        let start = rng.gen_range(0..length - 1000);
+        genome[start..start + 1000].fill(b'N');
+    }
+    let start = reverse_complement(motif).repeat(ARRAY_COPIES);
+    let end = motif.repeat(ARRAY_COPIES);
+    genome.splice(0..0, start.bytes());
+    genome.extend(end.bytes());
+    genome
+}
+
+/// Run a scanner, returning the number of matches on both strands, and
+/// the fastest time taken.
+fn time<F: FnMut() -> usize>(mut scanner: F) -> (usize, Duration) {
+    let mut matches = 0;
+    let mut fastest = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        matches = scanner();
+        fastest = fastest.min(start.elapsed());
+    }
+    (matches, fastest)
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(42);
+    println!("genome\tmotif\tscanner\tmatches\tms\tMb/s");
+
+    for length in GENOME_LENGTHS {
+        for motif in MOTIFS {
+            let genome = genome(length, motif, &mut rng);
+            let revcomp = reverse_complement(motif);
+
+            // what find_motifs did for every motif, one strand at a time
+            let kmp = time(|| {
+                find_exact_motifs(motif.as_bytes(), &genome).len()
+                    + find_exact_motifs(revcomp.as_bytes(), &genome).len()
+            });
+            // what MotifScanner did, with both strands at once
+            let automaton = AhoCorasick::builder()
+                .ascii_case_insensitive(true)
+                .match_kind(MatchKind::Standard)
+                .build([motif, &revcomp])
+                .unwrap();
+            let aho_corasick = time(|| automaton.find_overlapping_iter(&genome).count());
+            // both strands at once, 2 bits a base
+            let scanner = BitScanner::new(&[motif, &revcomp]).unwrap();
+            let bits = time(|| scanner.find_all(&genome).iter().map(Vec::len).sum());
+
+            for (name, (matches, duration)) in [
+                ("KMP/BOM", kmp),
+                ("Aho-Corasick", aho_corasick),
+                ("2-bit", bits),
+            ] {
+                assert_eq!(matches, kmp.0, "{} disagrees", name);
+                println!(
+                    "{}\t{}\t{}\t{}\t{:.1}\t{:.0}",
+                    genome.len(),
+                    motif,
+                    name,
+                    matches,
+                    duration.as_secs_f64() * 1000.0,
+                    genome.len() as f64 / duration.as_secs_f64() / 1e6
+                );
+            }
+        }
+    }
+}
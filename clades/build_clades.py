@@ -67,8 +67,8 @@ print(
 print(
     """
     /// A function to get a telomeric repeat sequence
-    /// given a clade name.
-    pub fn return_telomere_sequence(clade: &str) -> TelomereSeq {
+    /// given a clade name, if it's one we have data for.
+    pub fn return_telomere_sequence(clade: &str) -> Option<TelomereSeq<'static>> {
         let result = match clade {"""
 )
 
@@ -97,8 +97,8 @@ for i, v in gb_order.items():
 
 print(
     """
-            _ => panic!("{} is not yet accounted for in this pipeline.", clade),
+            _ => return None,
         };
-        result
+        Some(result)
     }"""
 )
@@ -0,0 +1,1004 @@
+use anyhow::{bail, Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The magic number at the start (and end) of a BigWig file.
+const BIGWIG_MAGIC: u32 = 0x888F_FC26;
+/// The magic number of the chromosome B+ tree.
+const CHROM_TREE_MAGIC: u32 = 0x78CA_8C91;
+/// The magic number of the R-tree indexes.
+const INDEX_MAGIC: u32 = 0x2468_ACE0;
+/// The maximum number of items in a node of either tree.
+const BLOCK_SIZE: usize = 256;
+/// The maximum number of records compressed together in a section.
+const ITEMS_PER_SLOT: usize = 1024;
+/// The maximum number of zoom levels.
+const MAX_ZOOM_LEVELS: usize = 10;
+/// The size of the fixed header.
+const HEADER_SIZE: usize = 64;
+/// The size of each zoom level header.
+const ZOOM_HEADER_SIZE: usize = 24;
+/// The size of the summary of the whole file.
+const TOTAL_SUMMARY_SIZE: usize = 40;
+/// Where the summary of the whole file is written, after the header and
+/// space for as many zoom level headers as there could be.
+const TOTAL_SUMMARY_OFFSET: usize = HEADER_SIZE + ZOOM_HEADER_SIZE * MAX_ZOOM_LEVELS;
+/// Where the data starts, with the number of sections.
+const FULL_DATA_OFFSET: usize = TOTAL_SUMMARY_OFFSET + TOTAL_SUMMARY_SIZE;
+
+/// The `(start, end, value)` of an interval on a chromosome.
+type Interval = (u32, u32, f32);
+/// The `(chromosome, start, end, summary)` of a zoom level record.
+type ZoomRecord = (u32, u32, u32, Summary);
+
+/// Writes a BigWig file, the indexed and compressed binary form of a
+/// bedGraph, which genome browsers load much faster. The intervals of
+/// each chromosome are added in order, a chromosome at a time, and are
+/// compressed and written in sections as they're added. The indexes,
+/// and the zoom levels summarising the intervals, are written on
+/// [`BigWigWriter::finish`]. Until then, the zoom levels are kept in a
+/// temporary file next to the BigWig, as which of them are worth keeping
+/// is only known once all of the intervals have been added.
+///
+/// The size of each chromosome is the end of its last interval, unless
+/// it's given with [`BigWigWriter::set_length`].
+pub struct BigWigWriter {
+    /// Where the file is written.
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Where the next section is written.
+    offset: u64,
+    /// The name and size of each chromosome, numbered in the order
+    /// they were added.
+    chroms: Vec<(String, u32)>,
+    /// The end of the last interval of the last chromosome.
+    end: u32,
+    /// The number of intervals added.
+    intervals: usize,
+    /// The intervals of the last chromosome not yet written.
+    section: Vec<Interval>,
+    /// The sections written, to index them by.
+    items: Vec<IndexItem>,
+    /// The size of the largest section, before it was compressed.
+    max_block_size: usize,
+    /// The summary of all of the intervals.
+    total: Option<Summary>,
+    /// The zoom levels which might be kept, which are chosen from the
+    /// intervals of the first section.
+    zoom_levels: Option<Vec<ZoomLevel>>,
+    /// Where the zoom level sections are written until finished.
+    zoom_path: PathBuf,
+    zoom_file: BufWriter<File>,
+    zoom_offset: u64,
+}
+
+/// The summary of the values in a zoom level bin, or the whole file.
+#[derive(Debug, Clone, Copy)]
+struct Summary {
+    /// The number of bases with a value.
+    bases: u64,
+    min: f64,
+    max: f64,
+    /// The sum of the value of each base.
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Summary {
+    /// The summary of `bases` bases of the same value.
+    fn new(bases: u64, value: f64) -> Self {
+        Self {
+            bases,
+            min: value,
+            max: value,
+            sum: value * bases as f64,
+            sum_squares: value * value * bases as f64,
+        }
+    }
+
+    /// Add `bases` bases of the same value.
+    fn add(&mut self, bases: u64, value: f64) {
+        self.bases += bases;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value * bases as f64;
+        self.sum_squares += value * value * bases as f64;
+    }
+}
+
+/// A section of compressed data, and the region it covers, as an item
+/// of an R-tree index.
+#[derive(Debug, Clone, Copy)]
+struct IndexItem {
+    start_chrom: u32,
+    start: u32,
+    end_chrom: u32,
+    end: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// The intervals summarised in bins of `reduction` bases.
+struct ZoomLevel {
+    reduction: u32,
+    /// The records not yet written, the last of which might still grow.
+    records: Vec<ZoomRecord>,
+    /// The number of records.
+    count: usize,
+    /// The sections written to the temporary file.
+    items: Vec<IndexItem>,
+}
+
+impl ZoomLevel {
+    fn new(reduction: u32) -> Self {
+        Self {
+            reduction,
+            records: Vec::new(),
+            count: 0,
+            items: Vec::new(),
+        }
+    }
+
+    /// Add an interval on a chromosome, after those added already.
+    fn add(&mut self, chrom: u32, (start, end, value): Interval) {
+        let reduction = self.reduction;
+        let mut position = start;
+        while position < end {
+            let bin = position / reduction;
+            let bin_end = (bin as u64 + 1) * reduction as u64;
+            let overlap_end = bin_end.min(end as u64) as u32;
+            let bases = (overlap_end - position) as u64;
+            match self.records.last_mut() {
+                Some(last) if last.0 == chrom && last.1 / reduction == bin => {
+                    last.2 = overlap_end;
+                    last.3.add(bases, value as f64);
+                }
+                _ => {
+                    self.records.push((
+                        chrom,
+                        position,
+                        overlap_end,
+                        Summary::new(bases, value as f64),
+                    ));
+                    self.count += 1;
+                }
+            }
+            position = overlap_end;
+        }
+    }
+
+    /// Write the full sections of records which can't grow any more, or
+    /// `all` of the records at the end of a chromosome.
+    fn write<W: Write>(
+        &mut self,
+        file: &mut W,
+        offset: &mut u64,
+        all: bool,
+        max_block_size: &mut usize,
+    ) -> Result<()> {
+        let done = if all {
+            self.records.len()
+        } else {
+            (self.records.len().saturating_sub(1) / ITEMS_PER_SLOT) * ITEMS_PER_SLOT
+        };
+        for records in self.records[..done].chunks(ITEMS_PER_SLOT) {
+            let mut block = Vec::new();
+            for (id, start, end, summary) in records {
+                put_u32(&mut block, *id);
+                put_u32(&mut block, *start);
+                put_u32(&mut block, *end);
+                put_u32(&mut block, summary.bases as u32);
+                put_f32(&mut block, summary.min as f32);
+                put_f32(&mut block, summary.max as f32);
+                put_f32(&mut block, summary.sum as f32);
+                put_f32(&mut block, summary.sum_squares as f32);
+            }
+            let (first, last) = (records[0], records[records.len() - 1]);
+            self.items.push(write_block(
+                file,
+                offset,
+                (first.0, first.1, last.2),
+                &block,
+                max_block_size,
+            )?);
+        }
+        self.records.drain(..done);
+        Ok(())
+    }
+}
+
+impl BigWigWriter {
+    /// Create a BigWig file at `path`, along with the temporary file for
+    /// the zoom levels.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = BufWriter::new(
+            File::create(&path).with_context(|| format!("Could not create {}", path.display()))?,
+        );
+        // the headers, the total summary and the number of sections are
+        // filled in once finished
+        file.write_all(&[0; FULL_DATA_OFFSET + 8])?;
+
+        let zoom_path = PathBuf::from(format!("{}.zoom.tmp", path.display()));
+        let zoom_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&zoom_path)
+            .with_context(|| format!("Could not create {}", zoom_path.display()))?;
+
+        Ok(Self {
+            path,
+            file,
+            offset: (FULL_DATA_OFFSET + 8) as u64,
+            chroms: Vec::new(),
+            end: 0,
+            intervals: 0,
+            section: Vec::new(),
+            items: Vec::new(),
+            max_block_size: 0,
+            total: None,
+            zoom_levels: None,
+            zoom_path,
+            zoom_file: BufWriter::new(zoom_file),
+            zoom_offset: 0,
+        })
+    }
+
+    /// Add the `value` over `[start, end)` of a chromosome. The intervals
+    /// of a chromosome must be added in order, and can't overlap.
+    pub fn add(&mut self, chrom: &str, start: usize, end: usize, value: f32) -> Result<()> {
+        let (Ok(start), Ok(end)) = (u32::try_from(start), u32::try_from(end)) else {
+            bail!("Chromosome {} is too long for a BigWig file.", chrom)
+        };
+        if self.chroms.last().map(|(last, _)| last.as_str()) != Some(chrom) {
+            self.add_chrom(chrom)?;
+        }
+        if start < self.end {
+            bail!(
+                "Intervals on {} overlap or are out of order, which a BigWig file can't hold.",
+                chrom
+            )
+        }
+        self.end = end;
+        let (_, size) = self.chroms.last_mut().expect("added");
+        *size = (*size).max(end);
+
+        let bases = (end - start) as u64;
+        match self.total.as_mut() {
+            Some(total) => total.add(bases, value as f64),
+            None => self.total = Some(Summary::new(bases, value as f64)),
+        }
+        self.intervals += 1;
+        self.section.push((start, end, value));
+        if self.section.len() == ITEMS_PER_SLOT {
+            self.write_section(false)?;
+        }
+        Ok(())
+    }
+
+    /// Set the size of a chromosome, for when it's longer than the end of
+    /// its last interval. A chromosome without any intervals is added.
+    pub fn set_length(&mut self, chrom: &str, length: usize) -> Result<()> {
+        let Ok(length) = u32::try_from(length) else {
+            bail!("Chromosome {} is too long for a BigWig file.", chrom)
+        };
+        if !self.chroms.iter().any(|(name, _)| name == chrom) {
+            self.add_chrom(chrom)?;
+        }
+        let (_, size) = self
+            .chroms
+            .iter_mut()
+            .find(|(name, _)| name == chrom)
+            .expect("added");
+        *size = (*size).max(length);
+        Ok(())
+    }
+
+    /// Start the next chromosome, once the last one is written.
+    fn add_chrom(&mut self, chrom: &str) -> Result<()> {
+        if self.chroms.iter().any(|(name, _)| name == chrom) {
+            bail!(
+                "The intervals on {} aren't together, which a BigWig file needs.",
+                chrom
+            )
+        }
+        if u32::try_from(self.chroms.len()).is_err() {
+            bail!("Too many chromosomes for a BigWig file.")
+        }
+        self.write_section(true)?;
+        self.chroms.push((chrom.to_string(), 0));
+        self.end = 0;
+        Ok(())
+    }
+
+    /// Compress and write the intervals added since the last section, and
+    /// summarise them into the zoom levels, writing their records which are
+    /// done, or `all` of them at the end of a chromosome.
+    fn write_section(&mut self, all: bool) -> Result<()> {
+        if !self.section.is_empty() {
+            let id = (self.chroms.len() - 1) as u32;
+            let section = std::mem::take(&mut self.section);
+            let mut block = Vec::new();
+            put_u32(&mut block, id);
+            put_u32(&mut block, section[0].0);
+            put_u32(&mut block, section[section.len() - 1].1);
+            // item step and span are only for fixed or variable step
+            put_u32(&mut block, 0);
+            put_u32(&mut block, 0);
+            // bedGraph type, reserved
+            block.extend_from_slice(&[1, 0]);
+            put_u16(&mut block, section.len() as u16);
+            for (start, end, value) in &section {
+                put_u32(&mut block, *start);
+                put_u32(&mut block, *end);
+                put_f32(&mut block, *value);
+            }
+            self.items.push(write_block(
+                &mut self.file,
+                &mut self.offset,
+                (id, section[0].0, section[section.len() - 1].1),
+                &block,
+                &mut self.max_block_size,
+            )?);
+
+            let zoom_levels = self
+                .zoom_levels
+                .get_or_insert_with(|| zoom_reductions(&section).map(ZoomLevel::new).collect());
+            for level in zoom_levels.iter_mut() {
+                for interval in &section {
+                    level.add(id, *interval);
+                }
+            }
+        }
+        for level in self.zoom_levels.iter_mut().flatten() {
+            level.write(
+                &mut self.zoom_file,
+                &mut self.zoom_offset,
+                all,
+                &mut self.max_block_size,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the rest of the intervals, then the indexes, the zoom levels
+    /// which halve the number of records of the level before, and the
+    /// chromosomes. Then go back to fill in the header.
+    pub fn finish(mut self) -> Result<()> {
+        self.write_section(true)?;
+
+        let full_index_offset = self.offset;
+        let mut buf = Vec::new();
+        write_index(&mut buf, full_index_offset, &self.items);
+
+        // copy the zoom levels worth keeping from the temporary file
+        self.zoom_file.flush()?;
+        let zoom_file = self.zoom_file.get_mut();
+        let mut zoom_headers = Vec::new();
+        let mut previous = self.intervals;
+        for level in self.zoom_levels.iter().flatten() {
+            if level.count * 2 > previous {
+                break;
+            }
+            previous = level.count;
+            let data_offset = full_index_offset + buf.len() as u64;
+            put_u32(&mut buf, level.count as u32);
+            let mut items = Vec::with_capacity(level.items.len());
+            for item in &level.items {
+                let mut block = vec![0; item.size as usize];
+                zoom_file.seek(SeekFrom::Start(item.offset))?;
+                zoom_file.read_exact(&mut block)?;
+                items.push(IndexItem {
+                    offset: full_index_offset + buf.len() as u64,
+                    ..*item
+                });
+                buf.extend_from_slice(&block);
+            }
+            let index_offset = full_index_offset + buf.len() as u64;
+            write_index(&mut buf, full_index_offset, &items);
+            zoom_headers.push((level.reduction, data_offset, index_offset));
+        }
+
+        let chrom_tree_offset = full_index_offset + buf.len() as u64;
+        write_chrom_tree(&mut buf, full_index_offset, &self.chroms);
+        put_u32(&mut buf, BIGWIG_MAGIC);
+        self.file.write_all(&buf)?;
+
+        // now the offsets are known, fill in the headers
+        let mut header = Vec::with_capacity(FULL_DATA_OFFSET + 8);
+        put_u32(&mut header, BIGWIG_MAGIC);
+        put_u16(&mut header, 4);
+        put_u16(&mut header, zoom_headers.len() as u16);
+        put_u64(&mut header, chrom_tree_offset);
+        put_u64(&mut header, FULL_DATA_OFFSET as u64);
+        put_u64(&mut header, full_index_offset);
+        // field count and defined field count, only for BigBed
+        put_u16(&mut header, 0);
+        put_u16(&mut header, 0);
+        // no autoSql
+        put_u64(&mut header, 0);
+        put_u64(&mut header, TOTAL_SUMMARY_OFFSET as u64);
+        put_u32(&mut header, self.max_block_size as u32);
+        // no extension header
+        put_u64(&mut header, 0);
+        for (reduction, data_offset, index_offset) in zoom_headers {
+            put_u32(&mut header, reduction);
+            put_u32(&mut header, 0);
+            put_u64(&mut header, data_offset);
+            put_u64(&mut header, index_offset);
+        }
+        header.resize(TOTAL_SUMMARY_OFFSET, 0);
+        let total = self.total.unwrap_or(Summary::new(0, 0.0));
+        put_u64(&mut header, total.bases);
+        put_f64(&mut header, total.min);
+        put_f64(&mut header, total.max);
+        put_f64(&mut header, total.sum);
+        put_f64(&mut header, total.sum_squares);
+        put_u64(&mut header, self.items.len() as u64);
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file
+            .flush()
+            .with_context(|| format!("Could not write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Drop for BigWigWriter {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.zoom_path);
+    }
+}
+
+/// The reductions of the zoom levels which might be kept, each four times
+/// coarser than the last, starting from four times the mean size of the
+/// (first) `intervals`.
+fn zoom_reductions(intervals: &[Interval]) -> impl Iterator<Item = u32> {
+    let mean_span =
+        intervals.iter().map(|i| (i.1 - i.0) as u64).sum::<u64>() / intervals.len().max(1) as u64;
+    std::iter::successors(Some(mean_span.max(1) * 4), |reduction| Some(reduction * 4))
+        .take_while(|reduction| *reduction <= u32::MAX as u64)
+        .take(MAX_ZOOM_LEVELS)
+        .map(|reduction| reduction as u32)
+}
+
+/// The number of nodes at each level of a tree of `items` items, with up
+/// to `block_size` items (or child nodes) per node, from the leaves up.
+fn tree_levels(items: usize, block_size: usize) -> Vec<usize> {
+    let mut levels = vec![items.div_ceil(block_size).max(1)];
+    while levels[levels.len() - 1] > 1 {
+        levels.push(levels[levels.len() - 1].div_ceil(block_size));
+    }
+    levels
+}
+
+/// Compress and write a block of data on a chromosome, from `start` to
+/// `end`, at `offset` in the file, keeping track of the largest
+/// uncompressed block. Returns the item to index the block by.
+fn write_block<W: Write>(
+    file: &mut W,
+    offset: &mut u64,
+    (chrom, start, end): (u32, u32, u32),
+    block: &[u8],
+    max_block_size: &mut usize,
+) -> Result<IndexItem> {
+    *max_block_size = (*max_block_size).max(block.len());
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(block)?;
+    let compressed = encoder.finish()?;
+    file.write_all(&compressed)?;
+    let item = IndexItem {
+        start_chrom: chrom,
+        start,
+        end_chrom: chrom,
+        end,
+        offset: *offset,
+        size: compressed.len() as u64,
+    };
+    *offset += item.size;
+    Ok(item)
+}
+
+/// Write the B+ tree which maps chromosome names to their numbers and
+/// sizes, where the `buf` is written at `base` in the file.
+fn write_chrom_tree(buf: &mut Vec<u8>, base: u64, chroms: &[(String, u32)]) {
+    // the keys are sorted, but the chromosomes keep their numbers
+    let mut chroms: Vec<(&String, u32, u32)> = chroms
+        .iter()
+        .enumerate()
+        .map(|(id, (chrom, size))| (chrom, id as u32, *size))
+        .collect();
+    chroms.sort_unstable();
+    let block_size = chroms.len().clamp(1, BLOCK_SIZE);
+    let key_size = chroms.iter().map(|(c, _, _)| c.len()).max().unwrap_or(1);
+
+    put_u32(buf, CHROM_TREE_MAGIC);
+    put_u32(buf, block_size as u32);
+    put_u32(buf, key_size as u32);
+    // the value is the chromosome number and size
+    put_u32(buf, 8);
+    put_u64(buf, chroms.len() as u64);
+    put_u64(buf, 0);
+
+    let key = |buf: &mut Vec<u8>, chrom: &str| {
+        buf.extend_from_slice(chrom.as_bytes());
+        buf.resize(buf.len() + key_size - chrom.len(), 0);
+    };
+    // leaves and internal nodes are the same size
+    let node_size = 4 + block_size * (key_size + 8);
+    let levels = tree_levels(chroms.len(), block_size);
+    let mut level_offset = buf.len();
+    for (level, nodes) in levels.iter().enumerate().rev() {
+        // the items under each node at this level
+        let span = block_size.pow(level as u32 + 1);
+        let next_level_offset = level_offset + nodes * node_size;
+        for node in 0..*nodes {
+            let node_start = buf.len();
+            let first = node * span;
+            if level == 0 {
+                let items = &chroms[first.min(chroms.len())..(first + span).min(chroms.len())];
+                buf.extend_from_slice(&[1, 0]);
+                put_u16(buf, items.len() as u16);
+                for (chrom, id, size) in items {
+                    key(buf, chrom);
+                    put_u32(buf, *id);
+                    put_u32(buf, *size);
+                }
+            } else {
+                let child_span = span / block_size;
+                let children: Vec<usize> = (node * block_size..(node + 1) * block_size)
+                    .take_while(|child| child * child_span < chroms.len())
+                    .collect();
+                buf.extend_from_slice(&[0, 0]);
+                put_u16(buf, children.len() as u16);
+                for child in children {
+                    key(buf, chroms[child * child_span].0);
+                    put_u64(buf, base + (next_level_offset + child * node_size) as u64);
+                }
+            }
+            buf.resize(node_start + node_size, 0);
+        }
+        level_offset = next_level_offset;
+    }
+}
+
+/// Write the R-tree which indexes the (sorted) blocks by their region,
+/// where the `buf` is written at `base` in the file.
+fn write_index(buf: &mut Vec<u8>, base: u64, items: &[IndexItem]) {
+    // the index follows the data
+    let end_offset = base + buf.len() as u64;
+    put_u32(buf, INDEX_MAGIC);
+    put_u32(buf, BLOCK_SIZE as u32);
+    put_u64(buf, items.len() as u64);
+    let (first, last) = match (items.first(), items.last()) {
+        (Some(first), Some(last)) => ((first.start_chrom, first.start), (last.end_chrom, last.end)),
+        _ => ((0, 0), (0, 0)),
+    };
+    put_u32(buf, first.0);
+    put_u32(buf, first.1);
+    put_u32(buf, last.0);
+    put_u32(buf, last.1);
+    put_u64(buf, end_offset);
+    put_u32(buf, ITEMS_PER_SLOT as u32);
+    put_u32(buf, 0);
+
+    // leaves hold the offset and size of each block, and internal
+    // nodes the offset of each child node
+    let leaf_size = 4 + BLOCK_SIZE * 32;
+    let internal_size = 4 + BLOCK_SIZE * 24;
+    let levels = tree_levels(items.len(), BLOCK_SIZE);
+    let mut level_offset = buf.len();
+    for (level, nodes) in levels.iter().enumerate().rev() {
+        let span = BLOCK_SIZE.pow(level as u32 + 1);
+        let node_size = if level == 0 { leaf_size } else { internal_size };
+        let child_size = if level == 1 { leaf_size } else { internal_size };
+        let next_level_offset = level_offset + nodes * node_size;
+        for node in 0..*nodes {
+            let node_start = buf.len();
+            let first = node * span;
+            if level == 0 {
+                let leaf = &items[first.min(items.len())..(first + span).min(items.len())];
+                buf.extend_from_slice(&[1, 0]);
+                put_u16(buf, leaf.len() as u16);
+                for item in leaf {
+                    put_bounds(buf, item, item);
+                    put_u64(buf, item.offset);
+                    put_u64(buf, item.size);
+                }
+            } else {
+                let child_span = span / BLOCK_SIZE;
+                let children: Vec<usize> = (node * BLOCK_SIZE..(node + 1) * BLOCK_SIZE)
+                    .take_while(|child| child * child_span < items.len())
+                    .collect();
+                buf.extend_from_slice(&[0, 0]);
+                put_u16(buf, children.len() as u16);
+                for child in children {
+                    let first = &items[child * child_span];
+                    let last = &items[((child + 1) * child_span).min(items.len()) - 1];
+                    put_bounds(buf, first, last);
+                    put_u64(buf, base + (next_level_offset + child * child_size) as u64);
+                }
+            }
+            buf.resize(node_start + node_size, 0);
+        }
+        level_offset = next_level_offset;
+    }
+}
+
+/// Write the region from the start of `first` to the end of `last`.
+fn put_bounds(buf: &mut Vec<u8>, first: &IndexItem, last: &IndexItem) {
+    put_u32(buf, first.start_chrom);
+    put_u32(buf, first.start);
+    put_u32(buf, last.end_chrom);
+    put_u32(buf, last.end);
+}
+
+// BigWig files are written little endian.
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(buf: &mut Vec<u8>, value: f32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f64(buf: &mut Vec<u8>, value: f64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::collections::HashMap;
+
+    /// A BigWig file read back following the format, as a genome browser
+    /// would, without using any of the writer.
+    #[derive(Debug, Default)]
+    struct BigWig {
+        /// The name, number and size of each chromosome, in key order.
+        chroms: Vec<(String, u32, u32)>,
+        /// The `(chromosome, start, end, value)` of each interval.
+        intervals: Vec<(String, u32, u32, f32)>,
+        /// The reduction and number of bases summarised by each zoom level.
+        zoom_levels: Vec<(u32, u64)>,
+        /// The number of bases with a value, and their sum.
+        total: (u64, f64),
+    }
+
+    struct Bytes<'a>(&'a [u8]);
+
+    impl Bytes<'_> {
+        fn u16(&self, at: u64) -> u16 {
+            let at = at as usize;
+            u16::from_le_bytes(self.0[at..at + 2].try_into().unwrap())
+        }
+
+        fn u32(&self, at: u64) -> u32 {
+            let at = at as usize;
+            u32::from_le_bytes(self.0[at..at + 4].try_into().unwrap())
+        }
+
+        fn u64(&self, at: u64) -> u64 {
+            let at = at as usize;
+            u64::from_le_bytes(self.0[at..at + 8].try_into().unwrap())
+        }
+
+        fn f32(&self, at: u64) -> f32 {
+            f32::from_bits(self.u32(at))
+        }
+
+        fn f64(&self, at: u64) -> f64 {
+            f64::from_bits(self.u64(at))
+        }
+
+        /// The chromosomes under a node of the B+ tree.
+        fn chrom_node(&self, node: u64, key_size: u64, chroms: &mut Vec<(String, u32, u32)>) {
+            let is_leaf = self.0[node as usize] == 1;
+            for i in 0..self.u16(node + 2) as u64 {
+                let item = node + 4 + i * (key_size + 8);
+                let key = &self.0[item as usize..(item + key_size) as usize];
+                let name = String::from_utf8(key.to_vec()).unwrap();
+                if is_leaf {
+                    chroms.push((
+                        name.trim_end_matches('\0').to_string(),
+                        self.u32(item + key_size),
+                        self.u32(item + key_size + 4),
+                    ));
+                } else {
+                    self.chrom_node(self.u64(item + key_size), key_size, chroms);
+                }
+            }
+        }
+
+        /// The `(offset, size)` of the blocks under a node of an R-tree,
+        /// checking each is within the bounds of its parent.
+        fn index_node(
+            &self,
+            node: u64,
+            bounds: (u32, u32, u32, u32),
+            blocks: &mut Vec<(u64, u64)>,
+        ) {
+            let is_leaf = self.0[node as usize] == 1;
+            let item_size = if is_leaf { 32 } else { 24 };
+            for i in 0..self.u16(node + 2) as u64 {
+                let item = node + 4 + i * item_size;
+                let item_bounds = (
+                    self.u32(item),
+                    self.u32(item + 4),
+                    self.u32(item + 8),
+                    self.u32(item + 12),
+                );
+                assert!((item_bounds.0, item_bounds.1) >= (bounds.0, bounds.1));
+                assert!((item_bounds.2, item_bounds.3) <= (bounds.2, bounds.3));
+                if is_leaf {
+                    blocks.push((self.u64(item + 16), self.u64(item + 24)));
+                } else {
+                    self.index_node(self.u64(item + 16), item_bounds, blocks);
+                }
+            }
+        }
+
+        /// The uncompressed blocks indexed by the R-tree at `index`.
+        fn blocks(&self, index: u64, max_block_size: usize) -> Vec<Vec<u8>> {
+            assert_eq!(self.u32(index), INDEX_MAGIC);
+            let bounds = (
+                self.u32(index + 16),
+                self.u32(index + 20),
+                self.u32(index + 24),
+                self.u32(index + 28),
+            );
+            let mut blocks = Vec::new();
+            self.index_node(index + 48, bounds, &mut blocks);
+            assert_eq!(blocks.len() as u64, self.u64(index + 8));
+            blocks
+                .into_iter()
+                .map(|(offset, size)| {
+                    let mut block = Vec::new();
+                    ZlibDecoder::new(&self.0[offset as usize..(offset + size) as usize])
+                        .read_to_end(&mut block)
+                        .unwrap();
+                    assert!(block.len() <= max_block_size);
+                    block
+                })
+                .collect()
+        }
+    }
+
+    fn read_bigwig(path: &Path) -> BigWig {
+        let buf = std::fs::read(path).unwrap();
+        let bytes = Bytes(&buf);
+        assert_eq!(bytes.u32(0), BIGWIG_MAGIC);
+        assert_eq!(bytes.u32(buf.len() as u64 - 4), BIGWIG_MAGIC);
+        let max_block_size = bytes.u32(52) as usize;
+        let mut bigwig = BigWig::default();
+
+        let chrom_tree = bytes.u64(8);
+        assert_eq!(bytes.u32(chrom_tree), CHROM_TREE_MAGIC);
+        let key_size = bytes.u32(chrom_tree + 8) as u64;
+        bytes.chrom_node(chrom_tree + 32, key_size, &mut bigwig.chroms);
+        assert_eq!(bigwig.chroms.len() as u64, bytes.u64(chrom_tree + 16));
+        assert!(bigwig.chroms.windows(2).all(|w| w[0].0 < w[1].0));
+        let names: HashMap<u32, &str> = bigwig
+            .chroms
+            .iter()
+            .map(|(name, id, _)| (*id, name.as_str()))
+            .collect();
+
+        // the data, in bedGraph sections
+        let blocks = bytes.blocks(bytes.u64(24), max_block_size);
+        assert_eq!(bytes.u64(bytes.u64(16)), blocks.len() as u64);
+        for block in blocks {
+            let block = Bytes(&block);
+            assert_eq!(block.0[20], 1);
+            let chrom = names[&block.u32(0)];
+            for i in 0..block.u16(22) as u64 {
+                let record = 24 + i * 12;
+                bigwig.intervals.push((
+                    chrom.to_string(),
+                    block.u32(record),
+                    block.u32(record + 4),
+                    block.f32(record + 8),
+                ));
+            }
+        }
+
+        // the zoom levels, each summarising all of the data
+        for level in 0..bytes.u16(6) as u64 {
+            let header = 64 + level * 24;
+            let records = bytes.u32(bytes.u64(header + 8));
+            let blocks = bytes.blocks(bytes.u64(header + 16), max_block_size);
+            let mut count = 0;
+            let mut bases = 0;
+            for block in blocks {
+                let block = Bytes(&block);
+                for i in 0..(block.0.len() / 32) as u64 {
+                    bases += block.u32(i * 32 + 12) as u64;
+                    count += 1;
+                }
+            }
+            assert_eq!(count, records);
+            bigwig.zoom_levels.push((bytes.u32(header), bases));
+        }
+
+        let total = bytes.u64(44);
+        bigwig.total = (bytes.u64(total), bytes.f64(total + 24));
+        bigwig
+    }
+
+    /// A temporary path for the BigWig of each test.
+    fn temp_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tidk_test_{}_{}.bw", test, std::process::id()))
+    }
+
+    #[test]
+    fn test_tree_levels() {
+        assert_eq!(tree_levels(0, 256), vec![1]);
+        assert_eq!(tree_levels(256, 256), vec![1]);
+        assert_eq!(tree_levels(257, 256), vec![2, 1]);
+        assert_eq!(tree_levels(70000, 256), vec![274, 2, 1]);
+    }
+
+    #[test]
+    fn test_chrom_tree_bytes() {
+        // the layout in the BigWig spec, byte for byte
+        let mut buf = Vec::new();
+        write_chrom_tree(&mut buf, 0, &[("chr2".into(), 1000), ("chr1".into(), 500)]);
+        let expected: Vec<u8> = [
+            // magic, block size, key size, value size
+            &[0x91, 0x8c, 0xca, 0x78, 2, 0, 0, 0, 4, 0, 0, 0, 8, 0, 0, 0][..],
+            // item count, reserved
+            &[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            // a leaf, with two items
+            &[1, 0, 2, 0],
+            // sorted by name, keeping the numbers they were added with
+            &[b'c', b'h', b'r', b'1', 1, 0, 0, 0, 0xf4, 1, 0, 0],
+            &[b'c', b'h', b'r', b'2', 0, 0, 0, 0, 0xe8, 3, 0, 0],
+        ]
+        .concat();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_index_bytes() {
+        let mut buf = Vec::new();
+        let item = IndexItem {
+            start_chrom: 0,
+            start: 0,
+            end_chrom: 1,
+            end: 100,
+            offset: 400,
+            size: 50,
+        };
+        write_index(&mut buf, 1000, &[item]);
+        let mut expected: Vec<u8> = [
+            // magic, block size, item count
+            &[0xe0, 0xac, 0x68, 0x24, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0][..],
+            // the bounds of all the items
+            &[0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0],
+            // the end of the data, items per slot, reserved
+            &[0xe8, 3, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0],
+            // a leaf, with one item
+            &[1, 0, 1, 0],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0],
+            &[0x90, 1, 0, 0, 0, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        // and the empty slots of the leaf
+        expected.resize(48 + 4 + 256 * 32, 0);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_bigwig() {
+        let path = temp_path("bigwig");
+        let mut writer = BigWigWriter::create(&path).unwrap();
+        // chromosomes in any order, with gaps between the intervals, and
+        // longer than their last interval
+        writer.add("chr2", 0, 100, 1.0).unwrap();
+        writer.add("chr2", 200, 250, 0.5).unwrap();
+        writer.set_length("chr2", 1000).unwrap();
+        writer.add("chr1", 0, 100, 3.0).unwrap();
+        writer.add("chr1", 100, 150, 2.0).unwrap();
+        writer.set_length("chrM", 16569).unwrap();
+        writer.finish().unwrap();
+        assert!(!PathBuf::from(format!("{}.zoom.tmp", path.display())).exists());
+
+        let bigwig = read_bigwig(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            bigwig.chroms,
+            vec![
+                ("chr1".to_string(), 1, 150),
+                ("chr2".to_string(), 0, 1000),
+                ("chrM".to_string(), 2, 16569)
+            ]
+        );
+        let interval = |chrom: &str, start, end, value| (chrom.to_string(), start, end, value);
+        assert_eq!(
+            bigwig.intervals,
+            vec![
+                interval("chr2", 0, 100, 1.0),
+                interval("chr2", 200, 250, 0.5),
+                interval("chr1", 0, 100, 3.0),
+                interval("chr1", 100, 150, 2.0)
+            ]
+        );
+        assert_eq!(bigwig.total, (300, 525.0));
+    }
+
+    #[test]
+    fn test_bigwig_large() {
+        // enough sections and chromosomes for trees of more than one level
+        let path = temp_path("bigwig_large");
+        let mut writer = BigWigWriter::create(&path).unwrap();
+        let mut intervals = Vec::new();
+        for i in 0..300_000 {
+            intervals.push(("chr1".to_string(), i * 10, i * 10 + 10, (i % 7) as f32));
+        }
+        for i in 0..300 {
+            intervals.push((format!("contig{}", i), 5, 15, 1.0));
+        }
+        for (chrom, start, end, value) in &intervals {
+            writer
+                .add(chrom, *start as usize, *end as usize, *value)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let bigwig = read_bigwig(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bigwig.chroms.len(), 301);
+        assert_eq!(bigwig.chroms[0], ("chr1".to_string(), 0, 3_000_000));
+        assert_eq!(bigwig.intervals, intervals);
+        assert_eq!(bigwig.total.0, 3_003_000);
+        // each zoom level is coarser, and covers all of the bases
+        assert!(!bigwig.zoom_levels.is_empty());
+        assert_eq!(bigwig.zoom_levels[0].0, 40);
+        assert!(bigwig.zoom_levels.windows(2).all(|w| w[1].0 == w[0].0 * 4));
+        assert!(bigwig
+            .zoom_levels
+            .iter()
+            .all(|(_, bases)| *bases == 3_003_000));
+    }
+
+    #[test]
+    fn test_bigwig_empty() {
+        let path = temp_path("bigwig_empty");
+        BigWigWriter::create(&path).unwrap().finish().unwrap();
+
+        let bigwig = read_bigwig(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(bigwig.chroms.is_empty());
+        assert!(bigwig.intervals.is_empty());
+        assert!(bigwig.zoom_levels.is_empty());
+        assert_eq!(bigwig.total, (0, 0.0));
+    }
+
+    #[test]
+    fn test_bigwig_order() {
+        let path = temp_path("bigwig_order");
+        let mut writer = BigWigWriter::create(&path).unwrap();
+        writer.add("chr1", 0, 100, 1.0).unwrap();
+        assert!(writer.add("chr1", 50, 150, 1.0).is_err());
+        writer.add("chr2", 0, 100, 1.0).unwrap();
+        assert!(writer.add("chr1", 200, 300, 1.0).is_err());
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+        // the temporary file is removed, even if the writer isn't finished
+        assert!(!PathBuf::from(format!("{}.zoom.tmp", path.display())).exists());
+    }
+}
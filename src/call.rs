@@ -0,0 +1,330 @@
+use crate::{clades, gff::Gff3Writer, reader, utils, SubCommand};
+use anyhow::Result;
+use bio::io::fasta;
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, File};
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str;
+
+/// The entry point for `tidk call`.
+///
+/// Instead of counting motifs in windows, consecutive motif
+/// hits are merged into telomeric arrays, which are written
+/// out as a BED file.
+pub fn call(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
+    let input_fasta = matches
+        .get_one::<PathBuf>("fasta")
+        .expect("errored by clap");
+    let reference = reader::get_reference(matches);
+    let records = reader::records(input_fasta, reference.as_deref())?;
+
+    let telomeric_repeats = get_telomeric_repeats(matches)?;
+    eprintln!(
+        "[+]\tCalling telomeric arrays for repeat(s): {}",
+        telomeric_repeats.join(", ")
+    );
+
+    let max_gap = *matches
+        .get_one::<usize>("max-gap")
+        .expect("defaulted by clap");
+    let min_copies = *matches
+        .get_one::<usize>("min-copies")
+        .expect("defaulted by clap");
+    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("errored by clap");
+
+    // create directory for output
+    create_dir_all(outdir)?;
+
+    // create file
+    let file_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomeric_arrays.bed"
+    );
+    let call_file = File::create(file_name)?;
+    let mut call_file = LineWriter::new(call_file);
+    // and optionally as GFF3
+    let mut gff = match matches.get_flag("gff3") {
+        true => Some(Gff3Writer::create(format!(
+            "{}/{}{}",
+            outdir.display(),
+            output.display(),
+            "_telomeric_arrays.gff3"
+        ))?),
+        false => None,
+    };
+
+    // iterate over the fasta records
+    for result in records {
+        let record = result?;
+
+        write_arrays(
+            &record,
+            &mut call_file,
+            gff.as_mut(),
+            &telomeric_repeats,
+            max_gap,
+            min_copies,
+        )?;
+
+        eprintln!("[+]\tChromosome {} processed", record.id());
+    }
+    eprintln!("[+]\tFinished calling telomeric arrays.");
+
+    // optional log file
+    sc.log(matches)?;
+
+    Ok(())
+}
+
+/// Get the telomeric repeat(s) to call arrays with, either from
+/// the user supplied string, or from the clade table.
+pub fn get_telomeric_repeats(matches: &clap::ArgMatches) -> Result<Vec<String>> {
+    match matches.get_one::<String>("string") {
+        Some(s) => Ok(vec![s.to_uppercase()]),
+        None => {
+            let clade = matches.get_one::<String>("clade").expect("errored by clap");
+            Ok(clades::CladeTable::built_in().get(clade)?.to_vec())
+        }
+    }
+}
+
+/// The strand a telomeric array was found on, relative
+/// to the telomeric repeat that was queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Strand {
+    /// The telomeric repeat itself.
+    #[serde(rename = "+")]
+    Forward,
+    /// The reverse complement of the telomeric repeat.
+    #[serde(rename = "-")]
+    Reverse,
+}
+
+impl Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strand::Forward => write!(f, "+"),
+            Strand::Reverse => write!(f, "-"),
+        }
+    }
+}
+
+/// A contiguous run of telomeric repeat motifs on one strand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelomericArray {
+    /// Start of the array (0-based, inclusive).
+    pub start: usize,
+    /// End of the array (0-based, exclusive).
+    pub end: usize,
+    /// Which strand the motifs were found on.
+    pub strand: Strand,
+    /// Number of non-overlapping motif copies in the array.
+    pub copies: usize,
+    /// Proportion of the array covered by exact motif matches.
+    pub purity: f64,
+}
+
+impl TelomericArray {
+    /// The length of the array in base pairs.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the array has zero length.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Merge the (sorted) indexes of motif hits into arrays. Hits separated
+/// by at most `max_gap` bases are merged, which tolerates the odd
+/// mismatched or variant copy. Arrays with fewer than `min_copies`
+/// copies are discarded.
+pub fn merge_hits(
+    indexes: &[usize],
+    motif_length: usize,
+    max_gap: usize,
+    min_copies: usize,
+    strand: Strand,
+) -> Vec<TelomericArray> {
+    let mut arrays = Vec::new();
+
+    let mut iter = indexes.iter();
+    let first = match iter.next() {
+        Some(f) => *f,
+        None => return arrays,
+    };
+
+    // the array currently being extended
+    let mut start = first;
+    let mut end = first + motif_length;
+    let mut copies = 1;
+    let mut covered = motif_length;
+
+    for &index in iter {
+        let hit_end = index + motif_length;
+        if index <= end + max_gap {
+            // only count the bases not yet covered by previous hits
+            covered += hit_end - index.max(end).min(hit_end);
+            // and only count copies which do not overlap the previous one
+            if index >= end {
+                copies += 1;
+            }
+            end = end.max(hit_end);
+        } else {
+            if copies >= min_copies {
+                arrays.push(TelomericArray {
+                    start,
+                    end,
+                    strand,
+                    copies,
+                    purity: covered as f64 / (end - start) as f64,
+                });
+            }
+            start = index;
+            end = hit_end;
+            copies = 1;
+            covered = motif_length;
+        }
+    }
+    // and the last array
+    if copies >= min_copies {
+        arrays.push(TelomericArray {
+            start,
+            end,
+            strand,
+            copies,
+            purity: covered as f64 / (end - start) as f64,
+        });
+    }
+
+    arrays
+}
+
+/// Find all the telomeric arrays in a sequence for a given telomeric
+/// repeat, on both strands. Arrays are returned sorted by start position.
+pub fn call_arrays(
+    sequence: &str,
+    telomeric_repeat: &str,
+    max_gap: usize,
+    min_copies: usize,
+) -> Vec<TelomericArray> {
+    let forward_telomeric_seq = telomeric_repeat.to_uppercase();
+    let reverse_telomeric_seq = utils::reverse_complement(&forward_telomeric_seq);
+    let telomeric_length = forward_telomeric_seq.len();
+
+    let forward_motif = utils::find_motifs(&forward_telomeric_seq, sequence);
+    let reverse_motif = utils::find_motifs(&reverse_telomeric_seq, sequence);
+
+    let mut arrays = merge_hits(
+        &forward_motif.indexes,
+        telomeric_length,
+        max_gap,
+        min_copies,
+        Strand::Forward,
+    );
+    // palindromic repeats would otherwise be called twice
+    if reverse_telomeric_seq != forward_telomeric_seq {
+        arrays.append(&mut merge_hits(
+            &reverse_motif.indexes,
+            telomeric_length,
+            max_gap,
+            min_copies,
+            Strand::Reverse,
+        ));
+    }
+    arrays.sort_by_key(|a| (a.start, a.end));
+
+    arrays
+}
+
+/// Call the arrays in a fasta record for each telomeric repeat, and
+/// write them to file as BED6+2. The name column holds the telomeric
+/// repeat, the score column the purity scaled to 0-1000, and the two
+/// extra columns the copy number and purity. They are also written to
+/// the GFF3, if there is one.
+fn write_arrays<T: std::io::Write>(
+    record: &fasta::Record,
+    file: &mut LineWriter<T>,
+    mut gff: Option<&mut Gff3Writer<File>>,
+    telomeric_repeats: &[String],
+    max_gap: usize,
+    min_copies: usize,
+) -> Result<()> {
+    let sequence = str::from_utf8(record.seq())?.to_uppercase();
+
+    for telomeric_repeat in telomeric_repeats {
+        let arrays = call_arrays(&sequence, telomeric_repeat, max_gap, min_copies);
+
+        for array in arrays {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}",
+                record.id(),
+                array.start,
+                array.end,
+                telomeric_repeat,
+                (array.purity * 1000.0).round() as usize,
+                array.strand,
+                array.copies,
+                array.purity
+            )?;
+            if let Some(gff) = gff.as_mut() {
+                gff.write_array(record.id(), telomeric_repeat, &array, 0)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_hits_gap() {
+        // two runs of three, the second with a missing copy
+        let indexes = [0, 5, 10, 100, 105, 115];
+        let arrays = merge_hits(&indexes, 5, 5, 1, Strand::Forward);
+
+        assert_eq!(arrays.len(), 2);
+        assert_eq!((arrays[0].start, arrays[0].end), (0, 15));
+        assert_eq!(arrays[0].copies, 3);
+        assert_eq!(arrays[0].purity, 1.0);
+        assert_eq!((arrays[1].start, arrays[1].end), (100, 120));
+        assert_eq!(arrays[1].copies, 3);
+        assert_eq!(arrays[1].purity, 0.75);
+    }
+
+    #[test]
+    fn test_merge_hits_min_copies() {
+        let indexes = [0, 5, 10, 100];
+        let arrays = merge_hits(&indexes, 5, 0, 2, Strand::Forward);
+
+        assert_eq!(arrays.len(), 1);
+        assert_eq!(arrays[0].copies, 3);
+    }
+
+    #[test]
+    fn test_call_arrays_strands() {
+        // reverse complement at the start, forward at the end
+        let sequence = "CCTAACCTAACCTAAGCATGCATGCATTTAGGTTAGGTTAGGTTAGG";
+        let arrays = call_arrays(sequence, "TTAGG", 0, 2);
+
+        assert_eq!(arrays.len(), 2);
+        assert_eq!(arrays[0].strand, Strand::Reverse);
+        assert_eq!((arrays[0].start, arrays[0].end), (0, 15));
+        assert_eq!(arrays[1].strand, Strand::Forward);
+        assert_eq!(arrays[1].copies, 4);
+        assert_eq!(arrays[1].end, sequence.len());
+    }
+}
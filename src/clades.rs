@@ -1,6 +1,12 @@
+use crate::utils::validate_dna_string;
+use anyhow::{bail, Context, Result};
 use std::{
     boxed::Box,
+    collections::BTreeMap,
     fmt::{self, Display},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
 };
 use tabled::{
     object::{Columns, Rows},
@@ -99,8 +105,8 @@ pub static CLADES: &[&str] = &[
 ];
 
 /// A function to get a telomeric repeat sequence
-/// given a clade name.
-pub fn return_telomere_sequence(clade: &str) -> TelomereSeq {
+/// given a clade name, if it's one we have data for.
+pub fn return_telomere_sequence(clade: &str) -> Option<TelomereSeq<'static>> {
     let result = match clade {
         "Accipitriformes" => TelomereSeq {
             clade: "Accipitriformes",
@@ -430,30 +436,208 @@ pub fn return_telomere_sequence(clade: &str) -> TelomereSeq {
             length: 1,
         },
 
-        _ => panic!("{} is not yet accounted for in this pipeline.", clade),
+        _ => return None,
     };
-    result
+    Some(result)
 }
 // automated input end
 
-/// Pretty print a table containing all the information about
-/// telomeric repeats that we currently have.
-pub fn print_table() {
-    let mut clade_vec = Vec::new();
+/// The clades and their telomeric repeats. This is the built in table,
+/// which can be added to, or replaced, by a user supplied database.
+#[derive(Debug, Clone, Default)]
+pub struct CladeTable {
+    clades: BTreeMap<String, Vec<String>>,
+}
+
+impl CladeTable {
+    /// The table compiled into `tidk`, from the curated database.
+    pub fn built_in() -> Self {
+        let clades = CLADES
+            .iter()
+            .filter_map(|clade| {
+                let telomere_seq = return_telomere_sequence(clade)?;
+                let repeats = telomere_seq.seq.0.iter().map(|e| e.to_string()).collect();
+                Some((clade.to_string(), repeats))
+            })
+            .collect();
+        Self { clades }
+    }
+
+    /// Read a database in the same format as the curated database
+    /// (`clades/curated.csv`), where each row is a species with its
+    /// `Order` and `Telomeric repeat`. Other columns are ignored, so
+    /// those two are enough. Repeats are grouped by order, and as for
+    /// the built in table, pure repeats (e.g. `AAAAAA` or `ACACAC`)
+    /// are left out.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Could not open the clade database {}", path.display()))?;
+        Self::from_reader(file)
+            .with_context(|| format!("Could not read the clade database {}", path.display()))
+    }
+
+    /// Read a database as in [`CladeTable::from_csv`], from any reader.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim() == name)
+                .with_context(|| format!("The clade database has no `{}` column.", name))
+        };
+        let (clade_column, repeat_column) = (column("Order")?, column("Telomeric repeat")?);
+
+        let mut clades: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (i, row) in reader.records().enumerate() {
+            let row = row?;
+            let clade = row.get(clade_column).unwrap_or_default().trim();
+            let repeat = row.get(repeat_column).unwrap_or_default().trim();
+            if clade.is_empty() || repeat.is_empty() {
+                continue;
+            }
+            // the header is the first line
+            let repeat =
+                validate_dna_string(repeat).with_context(|| format!("On line {}", i + 2))?;
+            if is_pure_repeat(&repeat) {
+                continue;
+            }
+            let repeats = clades.entry(clade.to_string()).or_default();
+            if !repeats.contains(&repeat) {
+                repeats.push(repeat);
+            }
+        }
+
+        if clades.is_empty() {
+            bail!("The clade database has no telomeric repeats.");
+        }
+        Ok(Self { clades })
+    }
+
+    /// The table for `tidk find`, which is the built in table, unless
+    /// a database is given with `--db`. The clades in the database are
+    /// added to the built in table, replacing any of the same name, or
+    /// with `--replace-db`, used on their own.
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Self> {
+        let Some(db) = matches.get_one::<PathBuf>("db") else {
+            return Ok(Self::built_in());
+        };
+        let table = Self::from_csv(db)?;
+
+        if matches.get_flag("replace-db") {
+            Ok(table)
+        } else {
+            let mut built_in = Self::built_in();
+            built_in.merge(table);
+            Ok(built_in)
+        }
+    }
+
+    /// Add the clades from another table, replacing any of the same name.
+    pub fn merge(&mut self, other: CladeTable) {
+        self.clades.extend(other.clades);
+    }
 
-    for clade in CLADES {
-        clade_vec.push(return_telomere_sequence(clade));
+    /// The telomeric repeats of a clade.
+    pub fn get(&self, clade: &str) -> Result<&[String]> {
+        match self.clades.get(clade) {
+            Some(repeats) => Ok(repeats),
+            None => bail!(
+                "{} is not a clade we have telomeric repeats for. Use `tidk find --print` to list the clades, or add it with `--db`.",
+                clade
+            ),
+        }
     }
 
-    eprintln!(
-        "{}",
-        Table::new(&clade_vec)
-            .with(
-                Modify::new(Rows::new(1..clade_vec.len() - 1)).with(Width::wrap(30).keep_words())
-            )
-            .with(Disable::column(Columns::new(2..3)))
-            .with(Panel::footer(
-                "This table is created from a curated database of repeats. This database can be found in its raw form here: https://github.com/tolkit/telomeric-identifier/tree/main/clades/curated.csv"
-            )).with(Width::wrap(60).keep_words())
-    );
+    /// Pretty print a table containing all the information about
+    /// telomeric repeats that we currently have.
+    pub fn print(&self) {
+        let repeats: Vec<Vec<&str>> = self
+            .clades
+            .values()
+            .map(|repeats| repeats.iter().map(String::as_str).collect())
+            .collect();
+        let clade_vec: Vec<TelomereSeq> = self
+            .clades
+            .keys()
+            .zip(&repeats)
+            .map(|(clade, repeats)| TelomereSeq {
+                clade,
+                seq: Seq(Box::new(&repeats[..])),
+                length: repeats.len(),
+            })
+            .collect();
+
+        eprintln!(
+            "{}",
+            Table::new(&clade_vec)
+                .with(
+                    Modify::new(Rows::new(1..)).with(Width::wrap(30).keep_words())
+                )
+                .with(Disable::column(Columns::new(2..3)))
+                .with(Panel::footer(
+                    "This table is created from a curated database of repeats. This database can be found in its raw form here: https://github.com/tolkit/telomeric-identifier/tree/main/clades/curated.csv"
+                )).with(Width::wrap(60).keep_words())
+        );
+    }
+}
+
+/// Whether a repeat is a single base repeated, or a shorter repeat
+/// repeated, which aren't telomeric repeats. As in
+/// `clades/build_clades.py`, a repeat is made of a shorter one if it
+/// can be found inside itself doubled, other than at either end.
+fn is_pure_repeat(repeat: &str) -> bool {
+    let doubled = repeat.repeat(2);
+    repeat.len() < 2 || doubled[1..doubled.len() - 1].contains(repeat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_built_in() {
+        let table = CladeTable::built_in();
+        assert_eq!(table.get("Lepidoptera").unwrap(), ["AACCT"]);
+        assert!(table.get("Lepidopterans").is_err());
+        assert!(return_telomere_sequence("Lepidopterans").is_none());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let csv = "Phylum,Order,Family,Species,Telomeric repeat,Notes,Ref,Plot?\n\
+             Arthropoda,Lepidoptera,Nymphalidae,Vanessa cardui,AACCCT,G,\"Smith, J. (2023)\",\n\
+             Arthropoda,Lepidoptera,Pieridae,Pieris napi,aacct,G,,\n\
+             Arthropoda,Lepidoptera,Pieridae,Pieris rapae,AACCT,G,,\n\
+             Arthropoda,Newptera,Newidae,Newus newus,ACACAC,G,,\n\
+             Arthropoda,Newptera,Newidae,Newus novus,AACCCTA,G,,\n";
+        let db = CladeTable::from_reader(Cursor::new(csv)).unwrap();
+        assert_eq!(db.get("Lepidoptera").unwrap(), ["AACCCT", "AACCT"]);
+        assert_eq!(db.get("Newptera").unwrap(), ["AACCCTA"]);
+
+        let mut table = CladeTable::built_in();
+        table.merge(db);
+        assert_eq!(table.get("Lepidoptera").unwrap(), ["AACCCT", "AACCT"]);
+        assert_eq!(table.get("Newptera").unwrap(), ["AACCCTA"]);
+        assert_eq!(table.get("Apiales").unwrap(), ["AAACCCT"]);
+
+        let invalid = "Order,Telomeric repeat\nLepidoptera,AACCXT\n";
+        assert!(CladeTable::from_reader(Cursor::new(invalid)).is_err());
+        let no_column = "Order,Repeat\nLepidoptera,AACCT\n";
+        assert!(CladeTable::from_reader(Cursor::new(no_column)).is_err());
+        assert!(CladeTable::from_csv("tidk_test_missing_clades.csv").is_err());
+    }
+
+    #[test]
+    fn test_is_pure_repeat() {
+        assert!(is_pure_repeat("A"));
+        assert!(is_pure_repeat("AAAAAA"));
+        assert!(is_pure_repeat("ACACAC"));
+        assert!(is_pure_repeat("AACAAC"));
+        assert!(!is_pure_repeat("AACCCT"));
+        assert!(!is_pure_repeat("AACAACA"));
+    }
 }
@@ -1,21 +1,32 @@
-use crate::{utils, SubCommand};
+use crate::reader::{self, Region};
+use crate::{call::Strand, summary, utils, SubCommand};
 use anyhow::bail;
 use anyhow::Result;
-use bio::io::fasta;
 use itertools::Itertools;
 use rayon::prelude::*;
+use serde::Serialize;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
 use std::str;
 use std::sync::mpsc::channel;
-
-// when distance == 1, we get lower estimate of telomeric repeat number
-// than if we use distance == 0.1
-// it's somehow not splitting RepeatPositions correctly.
+use std::sync::Mutex;
 
 static REPEAT_PERIOD_THRESHOLD: usize = 3;
 
+/// The mean number of copies at each chromosome end, and the mean
+/// length of the runs, at which a candidate gets full marks for them.
+/// Both are scored on a log scale.
+const FULL_COPY_NUMBER: f64 = 1000.0;
+const FULL_ARRAY_LENGTH: f64 = 10000.0;
+/// Scores at or above these are called likely telomeric, or possible.
+const LIKELY_SCORE: f64 = 0.7;
+const POSSIBLE_SCORE: f64 = 0.4;
+
 /// The function called from `tidk explore`. It takes the [`clap::Argmatches`]
 /// from the user and also a [`SubCommand`].
 pub fn explore(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
@@ -44,91 +55,72 @@ pub fn explore(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     }
 
     let verbose = matches.get_flag("verbose");
+    let format = matches
+        .get_one::<String>("format")
+        .expect("defaulted by clap");
 
-    // to report the telomeres...
-    let mut output_vec: Vec<RepeatPositions> = Vec::new();
     // i.e. if you chose a length, as opposed to a minmum/maximum
-    if length > 0 {
+    let lengths = if length > 0 {
         eprintln!(
             "[+]\tExploring genome for potential telomeric repeats of length: {}",
             length
         );
-        let reader = fasta::Reader::from_file(input_fasta)?;
-
-        // try parallelising
-        let (sender, receiver) = channel();
-
-        reader
-            .records()
-            .par_bridge()
-            .for_each_with(sender, |s, record| {
-                let record = record.expect("[-]\tError during fasta record parsing.");
-                let id = record.id().to_owned();
-                let seq_len = record.seq().len();
-
-                let sequences = split_seq_by_distance(record, dist_from_chromosome_end, seq_len);
-
-                for sequence in sequences {
-                    let indexes = chunk_fasta(sequence, length, verbose, id.clone());
-
-                    if let Some(r) =
-                        calculate_indexes(indexes, length, verbose, id.clone(), threshold as usize)
-                    {
-                        s.send(r).expect("Did not send!");
-                    }
-                }
-            });
-
-        // this bit is a little chaotic
-        // collect output into a vector
-        let mut output = receiver.into_iter().collect();
-
-        output_vec.append(&mut output);
+        length..=length
     } else {
         // if a range was chosen.
         eprintln!(
             "[+]\tExploring genome for potential telomeric repeats between lengths {} and {}.",
             minimum, maximum
         );
-        for length in minimum..maximum + 1 {
-            eprintln!("[+]\t\tFinding telomeric repeat length: {}", length);
-
-            // have to call reader in the loop, as otherwise `reader` doesn't live long enough.
-            // I expect it's not an expensive call anyway.
-            let reader = fasta::Reader::from_file(input_fasta)?;
-
-            // try parallelising
-            let (sender, receiver) = channel();
-            reader
-                .records()
-                .par_bridge()
-                .for_each_with(sender, |s, record| {
-                    let record = record.expect("[-]\tError during fasta record parsing.");
-                    let id = record.id().to_owned();
-                    let seq_len = record.seq().len();
-
-                    let sequences =
-                        split_seq_by_distance(record, dist_from_chromosome_end, seq_len);
-
-                    for sequence in sequences {
-                        let indexes = chunk_fasta(sequence, length, verbose, id.clone());
-
-                        if let Some(r) = calculate_indexes(
-                            indexes,
-                            length,
-                            verbose,
-                            id.clone(),
-                            threshold as usize,
-                        ) {
-                            s.send(r).expect("Did not send!");
-                        }
+        minimum..=maximum
+    };
+
+    // the input is only read once, as it may be coming from stdin.
+    // with an indexed fasta, only the regions asked for are read.
+    let regions = reader::get_regions(matches);
+    let reference = reader::get_reference(matches);
+    let records = reader::region_records(input_fasta, reference.as_deref(), regions.as_ref())?;
+
+    // try parallelising
+    let (sender, receiver) = channel();
+    // the sequences read, which may each be in more than one region
+    let sequence_ids = Mutex::new(HashSet::new());
+
+    records
+        .par_bridge()
+        .try_for_each_with(sender, |s, record| -> Result<()> {
+            let (region, record) = record?;
+            let id = record.id().to_owned();
+            let seq_len = record.seq().len();
+            sequence_ids.lock().unwrap().insert(id.clone());
+
+            // the regions replace the distance from the chromosome end
+            let sequences = match regions {
+                Some(_) => vec![(region, record.seq().to_vec())],
+                None => split_seq_by_distance(record, dist_from_chromosome_end, seq_len).to_vec(),
+            };
+
+            for length in lengths.clone() {
+                if verbose {
+                    eprintln!(
+                        "[+]\t\tFinding telomeric repeat length {} in {}",
+                        length, id
+                    );
+                }
+                for (region, sequence) in &sequences {
+                    if let Some(r) =
+                        calculate_indexes(sequence, length, verbose, region, threshold as usize)
+                    {
+                        s.send(r)?;
                     }
-                });
-            let mut output = receiver.iter().collect();
+                }
+            }
+            Ok(())
+        })?;
 
-            output_vec.append(&mut output);
-        }
-    }
+    // this bit is a little chaotic
+    // collect output into a vector
+    let output_vec: Vec<RepeatPositions> = receiver.into_iter().collect();
     eprintln!("[+]\tFinished searching genome");
     eprintln!("[+]\tGenerating output");
 
@@ -138,12 +130,19 @@ pub fn explore(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     }
 
     // print likely telomeric repeat
-    // costly calculation if threshold is too low.
-    let est = get_telomeric_repeat_estimates(&mut repeat_postitions)?;
-
-    println!("canonical_repeat_unit\tcount");
-    for (cru, count) in est {
-        println!("{}\t{}", cru, count);
+    let groups = repeat_postitions.make_canonical_groups();
+    let est = get_telomeric_repeat_estimates(&groups)?;
+    let sequences = sequence_ids.into_inner().unwrap().len();
+    let candidates = get_candidates(est, &groups, sequences);
+
+    match matches.get_one::<PathBuf>("output") {
+        Some(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            write_candidates(&candidates, format, &mut file)?;
+            file.flush()?;
+            eprintln!("[+]\tWritten to: {}", path.display());
+        }
+        None => write_candidates(&candidates, format, &mut io::stdout().lock())?,
     }
 
     // optional log file
@@ -152,101 +151,75 @@ pub fn explore(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     Ok(())
 }
 
+/// Take a proportion of the sequence from either end, along with
+/// the region of the sequence each came from.
 pub fn split_seq_by_distance(
     sequence: bio::io::fasta::Record,
     dist_from_chromosome_end: f64,
     seq_len: usize,
-) -> [Vec<u8>; 2] {
+) -> [(Region, Vec<u8>); 2] {
     let dist = (seq_len as f64 * dist_from_chromosome_end).ceil() as usize;
+    let region = |start, end| Region {
+        id: sequence.id().to_string(),
+        start,
+        end,
+        length: seq_len,
+    };
     let filtered_sequence1 = sequence.seq()[0..dist].to_vec();
     let filtered_sequence2 = sequence.seq()[(seq_len - dist)..].to_vec();
-    [filtered_sequence1, filtered_sequence2]
+    [
+        (region(0, dist), filtered_sequence1),
+        (region(seq_len - dist, seq_len), filtered_sequence2),
+    ]
 }
 
-/// A chunked fasta segment with a position and a sequence.
-/// We split the fasta into chunks of size k, where k is the
-/// potential telomeric repeat length. Consecutive iterations
-/// of these chunks are compared for equality.
-#[derive(Debug, PartialEq, Eq)]
-pub struct ChunkedFasta {
-    /// Position of the sequence in the
-    /// fasta file.
-    pub position: usize,
-    /// The sequence itself.
-    pub sequence: String,
+/// The end of a chromosome, i.e. which half of it a repeat is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum End {
+    #[serde(rename = "5'")]
+    Left,
+    #[serde(rename = "3'")]
+    Right,
 }
 
-/// Chunk a fasta into a [`Vec<ChunkedFasta>`], i.e. split a fasta into chunks
-/// and compare adjacent chunks for equality. Store the positions and sequences
-/// if they are equivalent.
-fn chunk_fasta(
-    sequence: Vec<u8>,
-    chunk_length: usize,
-    verbose: bool,
-    id: String,
-) -> Vec<ChunkedFasta> {
-    let sequence_len = sequence.len();
-    let chunks = sequence.chunks(chunk_length);
-    // catch edge cases where chunk length greater than sequence length.
-    if sequence_len <= chunk_length {
-        if verbose {
-            eprintln!(
-                "[-]\tChunk length ({}) greater than filtered sequence length ({}) for {}
-[-]\tConsider increasing proportion of chromosome length covered. Skipping.",
-                chunk_length, sequence_len, id
-            );
-        }
-        return vec![];
-    }
-
-    let chunks_plus_one = sequence.chunks(chunk_length).skip(1);
-
-    // store the index positions and adjacent equivalent sequences.
-    let mut indexes = Vec::new();
-    // need this otherwise we lose the position in the sequence.
-    let mut pos = 0;
-    let mut is_first_consecutive = true;
-
-    // this is the heavy lifting.
-    // can use the enumerate to check whether the position is < dist from start or > dist from end.
-    // FIXME: how do I check if this is the first in a run?
-    for (a, b) in chunks.zip(chunks_plus_one) {
-        if a == b {
-            if is_first_consecutive {
-                indexes.push(ChunkedFasta {
-                    position: pos,
-                    sequence: str::from_utf8(a).unwrap().to_uppercase(),
-                });
-                pos += chunk_length;
-                indexes.push(ChunkedFasta {
-                    position: pos,
-                    sequence: str::from_utf8(a).unwrap().to_uppercase(),
-                });
-                is_first_consecutive = false;
-            } else {
-                pos += chunk_length;
-                indexes.push(ChunkedFasta {
-                    position: pos,
-                    sequence: str::from_utf8(a).unwrap().to_uppercase(),
-                });
-            }
-        } else if a != b {
-            pos += chunk_length;
-            is_first_consecutive = true;
+impl Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            End::Left => write!(f, "5'"),
+            End::Right => write!(f, "3'"),
         }
     }
-    indexes
 }
 
+/// A run of consecutive copies of a repeat, in chromosome
+/// coordinates.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RepeatPosition {
     id: String,
     pub start: usize,
     pub end: usize,
     pub sequence: String,
+    /// The end of the chromosome the run is at.
+    pub chromosome_end: End,
 }
 
 impl RepeatPosition {
+    /// A run from `start` to `end` of the sequence of `region`.
+    fn new(region: &Region, start: usize, end: usize, sequence: &str) -> Self {
+        let (start, end) = (region.start + start, region.start + end);
+        let chromosome_end = if (start + end) / 2 < region.length / 2 {
+            End::Left
+        } else {
+            End::Right
+        };
+        Self {
+            id: region.id.clone(),
+            start,
+            end,
+            sequence: sequence.to_string(),
+            chromosome_end,
+        }
+    }
     fn get_count(&self) -> usize {
         (self.end - self.start) / self.sequence.len()
     }
@@ -278,80 +251,47 @@ impl RepeatPositions {
 
         Self(inner.to_vec())
     }
-    // group into HashMap<usize, Vec<RepeatPosition>>
-    // where usize is the length of the telomeric repeat
-    fn make_length_groups(&self) -> HashMap<usize, Vec<RepeatPosition>> {
+    // group into HashMap<String, Vec<&RepeatPosition>>
+    // where String is the canonical form of the telomeric repeat
+    fn make_canonical_groups(&self) -> HashMap<String, Vec<&RepeatPosition>> {
         let mut groups = HashMap::new();
         for el in &self.0 {
-            let length = el.sequence.len();
-            groups.entry(length).or_insert(Vec::new()).push(el.clone());
+            let canonical = utils::lex_min(&el.sequence);
+            groups.entry(canonical).or_insert(Vec::new()).push(el);
         }
         groups
     }
 }
 
-// logic messed up here - the start/end don't exclusively include telomeric repeats.
-// it's merging two consective runs, even if they are separated by non (canonical)-telomeric sequence.
+/// Find the runs of a repeat of length `k` in a sequence, and keep
+/// those with more than `frequency` copies which aren't simple repeats.
 fn calculate_indexes(
-    indexes: Vec<ChunkedFasta>,
-    chunk_length: usize,
+    sequence: &[u8],
+    k: usize,
     verbose: bool,
-    id: String,
+    region: &Region,
     frequency: usize,
 ) -> Option<RepeatPositions> {
-    // eprintln!("INDEXES: {:#?}", indexes);
-    let mut start = 0usize;
-    // let mut end = 0usize;
-
-    let mut collection: Vec<RepeatPosition> = Vec::new();
-
-    let mut iter = indexes.iter().zip(indexes.iter().skip(1)).peekable();
-
-    while let Some((
-        ChunkedFasta {
-            position: position1,
-            sequence: sequence1,
-        },
-        ChunkedFasta {
-            position: position2,
-            sequence: sequence2,
-        },
-    )) = iter.next()
-    {
-        if iter.peek().is_none() {
-            // this is techinically incorrect - as this will almost always
-            // overshoot the last index of the genome.
-            collection.push(RepeatPosition {
-                id: id.clone(),
-                start,
-                end: *position2 + chunk_length,
-                sequence: sequence1.to_string(),
-            });
-            // end = *position2 + chunk_length;
-            // eprintln!("FINAL:: START: {}\tEND: {}", start, end);
-            // FIXME: ITS HERE? ALSO NEED TO CHECK IF START/END IS DIFFERENT?
-        } else if sequence1 == sequence2 && (position2 - position1) == sequence1.len() {
-            // start = *position1;
-            // end = *position2;
-            // eprintln!("EQUAL:: START: {}\tEND: {}", start, end);
-            continue;
-        } else if !(sequence1 == sequence2 && (position2 - position1) == sequence1.len()) {
-            // eprintln!("UNEQUAL:: START: {}\tEND: {}", start, end);
-            collection.push(RepeatPosition {
-                id: id.clone(),
-                start,
-                end: *position1 + chunk_length,
-                sequence: sequence1.to_string(),
-            });
-            // end = *position1 + chunk_length;
-            start = *position2;
+    // catch edge cases where k is greater than the sequence length.
+    if sequence.len() <= k {
+        if verbose {
+            eprintln!(
+                "[-]\tRepeat length ({}) greater than filtered sequence length ({}) for {}
+[-]\tConsider increasing proportion of chromosome length covered. Skipping.",
+                k,
+                sequence.len(),
+                region.id
+            );
         }
+        return None;
     }
+
+    let collection = find_tandem_repeats(sequence, k, region);
     if collection.is_empty() {
         if verbose {
             eprintln!(
                 "[-]\t\tChromosome {}: No consecutive repeats of length {} were identified.",
-                id, chunk_length
+                region.id, k
             );
         }
         None
@@ -361,6 +301,90 @@ fn calculate_indexes(
     }
 }
 
+/// Find the tandem repeats with a period of `k` in a sequence, in any
+/// frame. Each base is compared with the base `k` further on, and a
+/// stretch of at least `k` matches is a run of the repeat. Consecutive
+/// stretches of the same repeat, separated by no more than `k + 1`
+/// mismatches, are joined, so a single variant copy doesn't split a run.
+fn find_tandem_repeats(sequence: &[u8], k: usize, region: &Region) -> Vec<RepeatPosition> {
+    let sequence = sequence.to_ascii_uppercase();
+    if sequence.len() <= k {
+        return vec![];
+    }
+    let is_match = |i: usize| sequence[i] == sequence[i + k] && sequence[i] != b'N';
+
+    // the start and end of each run, with its repeat unit
+    let mut runs: Vec<(usize, usize, RepeatUnit)> = Vec::new();
+    let mut i = 0;
+    while i < sequence.len() - k {
+        if !is_match(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < sequence.len() - k && is_match(i) {
+            i += 1;
+        }
+        if i - start < k {
+            continue;
+        }
+        // the stretch covers the bases it was compared with
+        let end = i + k;
+        let Some(unit) = repeat_unit(&sequence[start..end], k) else {
+            continue;
+        };
+        match runs.last_mut() {
+            Some((_, last_end, last_unit))
+                if last_unit.canonical == unit.canonical && start <= *last_end + 1 =>
+            {
+                *last_end = end
+            }
+            _ => runs.push((start, end, unit)),
+        }
+    }
+
+    runs.into_iter()
+        .filter(|(_, _, unit)| is_primitive(&unit.sequence))
+        .map(|(start, end, unit)| RepeatPosition::new(region, start, end, &unit.sequence))
+        .collect()
+}
+
+/// The repeat unit of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RepeatUnit {
+    /// The canonical form of the repeat, see [`utils::lex_min()`].
+    canonical: String,
+    /// The repeat as it first appears in the run.
+    sequence: String,
+}
+
+/// The repeat unit of a run with a period of `k`. The canonical forms
+/// of the k-mers at every offset in the run are counted, so the most
+/// common is the repeat, whatever frame the run starts in, and even if
+/// some copies vary.
+fn repeat_unit(run: &[u8], k: usize) -> Option<RepeatUnit> {
+    let mut counts: HashMap<String, (usize, &[u8])> = HashMap::new();
+    for kmer in run.windows(k) {
+        let canonical = utils::lex_min(str::from_utf8(kmer).ok()?);
+        counts.entry(canonical).or_insert((0, kmer)).0 += 1;
+    }
+    // break ties on the canonical form, so the result is the same every time
+    let (canonical, (_, kmer)) = counts
+        .into_iter()
+        .max_by(|(a, (a_count, _)), (b, (b_count, _))| a_count.cmp(b_count).then(b.cmp(a)))?;
+    Some(RepeatUnit {
+        canonical,
+        sequence: str::from_utf8(kmer).ok()?.to_string(),
+    })
+}
+
+/// Whether a repeat unit is not itself a tandem repeat of some shorter
+/// unit. A run of TTAGGG has a period of 12 as well as 6, for example,
+/// and should only be reported once.
+fn is_primitive(unit: &str) -> bool {
+    let doubled = unit.repeat(2);
+    !doubled[1..doubled.len() - 1].contains(unit)
+}
 /// check if a sequence looks like it is not
 /// a telomeric repeat
 fn check_telomeric_repeat(sequence: &str) -> bool {
@@ -368,82 +392,287 @@ fn check_telomeric_repeat(sequence: &str) -> bool {
     repeat_period < REPEAT_PERIOD_THRESHOLD
 }
 
-/// take two repeat sequences of the same length and
-/// return bool, if they represent the same canonical repeat
-/// and the canonical repeat
-fn test_repeats(repeat1: &RepeatPosition, repeat2: &RepeatPosition) -> (bool, Option<String>) {
-    let r1_seq = &repeat1.sequence;
-    let r2_seq = &repeat2.sequence;
-    let is_equal = utils::string_rotation(r1_seq, r2_seq)
-        || utils::string_rotation(&utils::reverse_complement(r1_seq), r2_seq)
-        || utils::string_rotation(r1_seq, &utils::reverse_complement(r2_seq));
-
-    if is_equal {
-        (is_equal, Some(utils::lms(r1_seq, r2_seq)))
-    } else {
-        (is_equal, None)
-    }
-}
-
 /// Takes the final aggregation of potential telomeric repeats across
-/// chromosomes and also potentially across different lengths and tries
-/// to find the most likely telomeric repeat. See [`utils::format_telomeric_repeat()`]
-/// for the explanation of the formatting.
-/// FIXME: we compare telomeric repeats of different lengths here! sort that out.
+/// chromosomes, grouped by their canonical form (so different lengths
+/// are never compared), and counts the copies of each, to find the
+/// most likely telomeric repeat.
 fn get_telomeric_repeat_estimates(
-    telomeric_repeats: &mut RepeatPositions,
+    groups: &HashMap<String, Vec<&RepeatPosition>>,
 ) -> Result<Vec<(String, i32)>> {
-    let groups = telomeric_repeats.make_length_groups();
-
-    // we need to compare all elements against all others
-    let mut map: HashMap<String, i32> = HashMap::new();
-
-    for (_, telomeric_repeats_i) in groups {
-        // deal with this separately, as if there's only one repeat
-        // estimated, we can't go further
-        if telomeric_repeats_i.len() == 1 {
-            let count = telomeric_repeats_i[0].get_count();
-            let seq = &telomeric_repeats_i[0].sequence;
-            map.insert(seq.clone(), count as i32);
-            continue;
+    let mut count_vec: Vec<(String, i32)> = groups
+        .iter()
+        .map(|(canonical, positions)| {
+            let count: usize = positions.iter().map(|p| p.get_count()).sum();
+            (canonical.clone(), count as i32)
+        })
+        .collect();
+    // ties are broken on the repeat, as the groups are unordered
+    count_vec.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    filter_count_vec(&mut count_vec)?;
+
+    Ok(count_vec)
+}
+
+/// A run of a [`Candidate`] repeat in the genome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CandidateArray {
+    /// The chromosome ID.
+    pub id: String,
+    /// The end of the chromosome the run is at.
+    pub chromosome_end: End,
+    /// The strand of the run, relative to the canonical repeat.
+    pub strand: Strand,
+    /// Whether the run is on the strand we expect at this end of
+    /// a chromosome; the C-rich strand at the 5' end and the G-rich
+    /// strand at the 3' end.
+    pub expected_orientation: bool,
+    /// Start of the run (0-based, inclusive).
+    pub start: usize,
+    /// End of the run (0-based, exclusive).
+    pub end: usize,
+    /// The repeat, as it was found in the sequence.
+    pub sequence: String,
+    /// The number of copies of the repeat in the run.
+    pub copies: usize,
+}
+
+impl CandidateArray {
+    /// A run of the canonical repeat, which the position must
+    /// be a rotation of, on either strand.
+    fn new(position: &RepeatPosition, canonical_repeat_unit: &str) -> Self {
+        let strand = if utils::string_rotation(&position.sequence, canonical_repeat_unit) {
+            Strand::Forward
+        } else {
+            Strand::Reverse
+        };
+        let is_left = position.chromosome_end == End::Left;
+        let expected_strand = summary::expected_strand(canonical_repeat_unit, is_left);
+
+        Self {
+            id: position.id.clone(),
+            chromosome_end: position.chromosome_end,
+            strand,
+            expected_orientation: strand == expected_strand,
+            start: position.start,
+            end: position.end,
+            sequence: position.sequence.clone(),
+            copies: position.get_count(),
         }
-        // so we don't compare the same thing twice.
-        let mut tracker: Vec<usize> = Vec::new();
-        // create all combinations of indices
-        let it = (0..telomeric_repeats_i.len()).combinations(2);
-
-        // iterate over combinations
-        for comb in it {
-            let first = &telomeric_repeats_i[comb[0]];
-            let second = &telomeric_repeats_i[comb[1]];
-
-            let (is_rotation, potential_sequence) = test_repeats(first, second);
-            // if the combination is a string rotation (or its reverse complement)
-            // then combine
-            if is_rotation {
-                let sequence = potential_sequence.unwrap();
-                // if comb[0] || comb[1] not in tracker...
-                // as we already added the contents of the tracked telomeric repeats
-                // we do not want to count them again.
-                let count = map
-                    // relies on the telomeric repeat string resolving to a 'canonical'
-                    // or unique form of the string, see utils::lms()
-                    .entry(sequence)
-                    .or_insert(first.get_count() as i32 + second.get_count() as i32);
-                if !tracker.contains(&comb[0]) && !tracker.contains(&comb[1]) {
-                    *count += first.get_count() as i32 + second.get_count() as i32;
-                }
-            }
-            tracker.push(comb[0]);
-            tracker.push(comb[1]);
+    }
+}
+
+/// A potential telomeric repeat, with the runs of it which were
+/// found in the genome.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    /// The repeat in its canonical form.
+    pub canonical_repeat_unit: String,
+    /// The count from [`get_telomeric_repeat_estimates()`].
+    pub count: i32,
+    /// The length of the repeat, i.e. the k used to find it.
+    pub k: usize,
+    /// The number of copies of the repeat over all its runs.
+    pub total_copies: usize,
+    /// The chromosomes with a run of the repeat.
+    pub chromosomes: Vec<String>,
+    /// The number of chromosomes with a run at the 5' end.
+    pub left_chromosomes: usize,
+    /// The number of chromosomes with a run at the 3' end.
+    pub right_chromosomes: usize,
+    /// The number of chromosomes with runs at both ends, as we
+    /// expect of a telomeric repeat.
+    pub both_ends_chromosomes: usize,
+    /// The proportion of copies in runs with the expected
+    /// orientation for their end of the chromosome.
+    pub orientation_consistency: f64,
+    /// How likely the candidate is to be the telomeric repeat.
+    pub score: f64,
+    /// The call made from the score.
+    pub call: Call,
+    /// The parts of the score.
+    pub scores: Scores,
+    /// The runs themselves.
+    pub positions: Vec<CandidateArray>,
+}
+
+/// Whether a candidate looks like a telomeric repeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Call {
+    #[serde(rename = "likely telomeric")]
+    Likely,
+    #[serde(rename = "possible")]
+    Possible,
+    #[serde(rename = "unlikely")]
+    Unlikely,
+}
+
+impl Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Call::Likely => write!(f, "likely telomeric"),
+            Call::Possible => write!(f, "possible"),
+            Call::Unlikely => write!(f, "unlikely"),
         }
     }
+}
 
-    let mut count_vec: Vec<_> = map.into_iter().collect();
-    count_vec.sort_by(|a, b| b.1.cmp(&a.1));
-    filter_count_vec(&mut count_vec)?;
+/// The evidence that a candidate is a telomeric repeat, each
+/// part from 0 to 1.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Scores {
+    /// The mean number of copies at the chromosome ends with
+    /// the repeat.
+    pub copy_number: f64,
+    /// The proportion of the sequences read with the repeat.
+    pub support: f64,
+    /// The proportion of the chromosomes with the repeat which
+    /// have it at both ends.
+    pub end_specificity: f64,
+    /// The orientation consistency of the runs.
+    pub strand_consistency: f64,
+    /// The mean length of the runs.
+    pub array_length: f64,
+}
 
-    Ok(count_vec)
+impl Scores {
+    /// The weighted sum of the parts, with the most weight on how
+    /// many chromosomes have the repeat.
+    fn total(&self) -> f64 {
+        0.3 * self.support
+            + 0.2 * self.end_specificity
+            + 0.2 * self.strand_consistency
+            + 0.15 * self.copy_number
+            + 0.15 * self.array_length
+    }
+}
+
+/// Score a quantity on a log scale, from 0 at one up to 1 at `full`.
+fn log_score(value: f64, full: f64) -> f64 {
+    (value.max(1.0).log10() / full.log10()).min(1.0)
+}
+
+/// Make the call from a score.
+fn call(score: f64) -> Call {
+    if score >= LIKELY_SCORE {
+        Call::Likely
+    } else if score >= POSSIBLE_SCORE {
+        Call::Possible
+    } else {
+        Call::Unlikely
+    }
+}
+
+/// Gather the runs of each estimated telomeric repeat, as
+/// [`Candidate`]s, and score them against the number of `sequences`
+/// read. The best scoring candidate comes first.
+fn get_candidates(
+    estimates: Vec<(String, i32)>,
+    groups: &HashMap<String, Vec<&RepeatPosition>>,
+    sequences: usize,
+) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = estimates
+        .into_iter()
+        .map(|(canonical_repeat_unit, count)| {
+            let mut positions: Vec<CandidateArray> = groups[&canonical_repeat_unit]
+                .iter()
+                .map(|p| CandidateArray::new(p, &canonical_repeat_unit))
+                .collect();
+            // the chromosomes are searched in parallel
+            positions.sort_by(|a, b| a.id.cmp(&b.id).then(a.start.cmp(&b.start)));
+
+            let chromosomes_at = |end: End| -> HashSet<&str> {
+                positions
+                    .iter()
+                    .filter(|p| p.chromosome_end == end)
+                    .map(|p| p.id.as_str())
+                    .collect()
+            };
+            let left = chromosomes_at(End::Left);
+            let right = chromosomes_at(End::Right);
+
+            let total_copies = positions.iter().map(|p| p.copies).sum();
+            let expected_copies: usize = positions
+                .iter()
+                .filter(|p| p.expected_orientation)
+                .map(|p| p.copies)
+                .sum();
+            let orientation_consistency = if total_copies > 0 {
+                expected_copies as f64 / total_copies as f64
+            } else {
+                0.0
+            };
+
+            let chromosomes: Vec<String> = positions.iter().map(|p| p.id.clone()).dedup().collect();
+            let both_ends_chromosomes = left.intersection(&right).count();
+            let ends = left.len() + right.len();
+            let array_length: usize = positions.iter().map(|p| p.end - p.start).sum();
+
+            let scores = Scores {
+                copy_number: log_score(total_copies as f64 / ends.max(1) as f64, FULL_COPY_NUMBER),
+                support: (chromosomes.len() as f64 / sequences.max(1) as f64).min(1.0),
+                end_specificity: both_ends_chromosomes as f64 / chromosomes.len().max(1) as f64,
+                strand_consistency: orientation_consistency,
+                array_length: log_score(
+                    array_length as f64 / positions.len().max(1) as f64,
+                    FULL_ARRAY_LENGTH,
+                ),
+            };
+            let score = scores.total();
+
+            Candidate {
+                k: canonical_repeat_unit.len(),
+                total_copies,
+                chromosomes,
+                left_chromosomes: left.len(),
+                right_chromosomes: right.len(),
+                both_ends_chromosomes,
+                orientation_consistency,
+                score,
+                call: call(score),
+                scores,
+                positions,
+                canonical_repeat_unit,
+                count,
+            }
+        })
+        .collect();
+
+    // stable, so ties keep the order of the counts
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+/// Write the candidates as a TSV, with a row for each, or as JSON
+/// with all of their runs.
+fn write_candidates<T: Write>(candidates: &[Candidate], format: &str, file: &mut T) -> Result<()> {
+    if format == "json" {
+        serde_json::to_writer_pretty(&mut *file, candidates)?;
+        writeln!(file)?;
+        return Ok(());
+    }
+
+    writeln!(
+        file,
+        "canonical_repeat_unit\tcount\tk\ttotal_copies\tchromosomes\tleft_chromosomes\tright_chromosomes\tboth_ends_chromosomes\torientation_consistency\tscore\tcall"
+    )?;
+    for candidate in candidates {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{:.3}\t{}",
+            candidate.canonical_repeat_unit,
+            candidate.count,
+            candidate.k,
+            candidate.total_copies,
+            candidate.chromosomes.len(),
+            candidate.left_chromosomes,
+            candidate.right_chromosomes,
+            candidate.both_ends_chromosomes,
+            candidate.orientation_consistency,
+            candidate.score,
+            candidate.call
+        )?;
+    }
+    Ok(())
 }
 
 /// Returns the shortest period of repetition in s.
@@ -517,7 +746,7 @@ mod tests {
 
     fn split_by_dist(genome: &str) -> [Vec<u8>; 2] {
         let record = bio::io::fasta::Record::with_attrs("id1", None, genome.as_bytes());
-        split_seq_by_distance(record, DIST_FROM_CHROM_END, genome.len())
+        split_seq_by_distance(record, DIST_FROM_CHROM_END, genome.len()).map(|(_, s)| s)
     }
 
     // GENOME/GENOME_2 are just two meta-repeats, so this should just be in half
@@ -535,73 +764,55 @@ mod tests {
         assert_eq!(left, "AACCTAACCTAACATATCGTAACCTAACCT")
     }
 
-    fn generate_chunks_left(genome: &str) -> Vec<ChunkedFasta> {
-        let left = &split_by_dist(genome)[0];
-        chunk_fasta(left.clone(), CHUNK_LENGTH, false, "".into())
+    fn region(sequence: &str) -> Region {
+        Region {
+            id: "test".into(),
+            start: 0,
+            end: sequence.len(),
+            length: sequence.len(),
+        }
     }
 
     #[test]
-    fn test_chunks_left() {
-        let chunks = generate_chunks_left(GENOME);
-        // in the left
-        assert_eq!(
-            chunks,
-            vec![
-                ChunkedFasta {
-                    position: 0,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 5,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 20,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 25,
-                    sequence: "AACCT".into()
-                }
-            ]
-        )
+    fn test_tandem_repeats_frame() {
+        // the array doesn't start at a multiple of k
+        let sequence = format!("GCA{}CA", "TTAGGG".repeat(20));
+        let repeats = find_tandem_repeats(sequence.as_bytes(), 6, &region(&sequence));
+        assert_eq!(repeats.len(), 1);
+        assert_eq!((repeats[0].start, repeats[0].end), (3, 123));
+        assert_eq!(repeats[0].sequence, "TTAGGG");
+        assert_eq!(repeats[0].get_count(), 20);
     }
 
-    fn generate_chunks_right() -> Vec<ChunkedFasta> {
-        let left = &split_by_dist(GENOME)[1];
-        chunk_fasta(left.clone(), CHUNK_LENGTH, false, "".into())
+    #[test]
+    fn test_tandem_repeats_variant() {
+        // a single variant copy doesn't split the array
+        let sequence = format!("{}TTAGCG{}", "TTAGGG".repeat(10), "ttaggg".repeat(10));
+        let repeats = find_tandem_repeats(sequence.as_bytes(), 6, &region(&sequence));
+        assert_eq!(repeats.len(), 1);
+        assert_eq!((repeats[0].start, repeats[0].end), (0, 126));
     }
 
     #[test]
-    fn test_chunks_right() {
-        let chunks = generate_chunks_right();
-        // in the right
-        assert_eq!(
-            chunks,
-            vec![
-                ChunkedFasta {
-                    position: 0,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 5,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 20,
-                    sequence: "AACCT".into()
-                },
-                ChunkedFasta {
-                    position: 25,
-                    sequence: "AACCT".into()
-                }
-            ]
-        )
+    fn test_tandem_repeats_period() {
+        // only reported at the shortest period
+        let sequence = "TTAGGG".repeat(20);
+        assert!(find_tandem_repeats(sequence.as_bytes(), 12, &region(&sequence)).is_empty());
+        assert!(find_tandem_repeats(sequence.as_bytes(), 5, &region(&sequence)).is_empty());
+        assert!(is_primitive("TTAGGG"));
+        assert!(!is_primitive("TTAGGGTTAGGG"));
+        assert!(is_primitive("ABCAB"));
     }
 
     fn generate_indexes_left(genome: &str) -> RepeatPositions {
-        let chunks = generate_chunks_left(genome);
-        calculate_indexes(chunks, CHUNK_LENGTH, false, "test".into(), 0).unwrap()
+        let left = &split_by_dist(genome)[0];
+        let region = Region {
+            id: "test".into(),
+            start: 0,
+            end: genome.len() / 2,
+            length: genome.len(),
+        };
+        calculate_indexes(left, CHUNK_LENGTH, false, &region, 0).unwrap()
     }
 
     #[test]
@@ -609,35 +820,176 @@ mod tests {
         let indices = generate_indexes_left(GENOME);
         assert_eq!(
             indices.0,
-            // i.e. there are 9 matches for AACCT
+            // the partial copies at the end of each run are included
             vec![
                 RepeatPosition {
                     id: "test".into(),
                     start: 0,
-                    end: 10,
-                    sequence: "AACCT".into()
+                    end: 13,
+                    sequence: "AACCT".into(),
+                    chromosome_end: End::Left
                 },
                 RepeatPosition {
                     id: "test".into(),
-                    start: 20,
+                    start: 19,
                     end: 30,
-                    sequence: "AACCT".into()
+                    sequence: "TAACC".into(),
+                    chromosome_end: End::Left
                 }
             ]
         )
     }
     #[test]
-    fn test_get_length_groups() {
+    fn test_get_canonical_groups() {
         let indices = generate_indexes_left(GENOME_2);
-        // we have AACCT 0-10, TAAAT 10-20, AACCT 20-30
-        let map_len = indices.make_length_groups().get(&5).unwrap().len();
-        assert_eq!(map_len, 3);
+        // we have AACCT 0-10, TAAAT 9-20, AACCT 19-30
+        let groups = indices.make_canonical_groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["AACCT"].len(), 2);
+        assert_eq!(groups["AAATT"].len(), 1);
     }
 
     #[test]
     fn test_get_telomeric_repeat_estimates() {
-        let mut indices = generate_indexes_left(GENOME_2);
-        let res = get_telomeric_repeat_estimates(&mut indices).unwrap();
-        assert_eq!(res, vec![("AACCT".to_string(), 4)]);
+        let indices = generate_indexes_left(GENOME_2);
+        let res = get_telomeric_repeat_estimates(&indices.make_canonical_groups()).unwrap();
+        // a repeat which occurs once is counted too
+        assert_eq!(
+            res,
+            vec![("AACCT".to_string(), 4), ("AAATT".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_get_telomeric_repeat_estimates_strands() {
+        let region = Region {
+            id: "test".into(),
+            start: 0,
+            end: 1000,
+            length: 1000,
+        };
+        // rotations and reverse complements of the same repeat are combined
+        let positions = RepeatPositions(vec![
+            RepeatPosition::new(&region, 0, 60, "CCCTAA"),
+            RepeatPosition::new(&region, 100, 130, "AACCCT"),
+            RepeatPosition::new(&region, 940, 1000, "TTAGGG"),
+            RepeatPosition::new(&region, 500, 550, "TTAGG"),
+        ]);
+        let res = get_telomeric_repeat_estimates(&positions.make_canonical_groups()).unwrap();
+        assert_eq!(
+            res,
+            vec![("AACCCT".to_string(), 25), ("AACCT".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_split_regions() {
+        let record = bio::io::fasta::Record::with_attrs("id1", None, GENOME.as_bytes());
+        let [(left, _), (right, _)] = split_seq_by_distance(record, 0.1, GENOME.len());
+        assert_eq!((left.start, left.end), (0, 6));
+        assert_eq!((right.start, right.end), (54, 60));
+        assert_eq!(right.length, 60);
+    }
+
+    #[test]
+    fn test_repeat_position_end() {
+        let region = Region {
+            id: "chr1".into(),
+            start: 900,
+            end: 1000,
+            length: 1000,
+        };
+        let position = RepeatPosition::new(&region, 10, 40, "TTAGG");
+        assert_eq!((position.start, position.end), (910, 940));
+        assert_eq!(position.chromosome_end, End::Right);
+    }
+
+    #[test]
+    fn test_get_candidates() {
+        let indices = generate_indexes_left(GENOME_2);
+        let groups = indices.make_canonical_groups();
+        let est = get_telomeric_repeat_estimates(&groups).unwrap();
+        let candidates = get_candidates(est, &groups, 1);
+
+        assert_eq!(candidates.len(), 2);
+        let candidate = &candidates[0];
+        assert_eq!(candidate.k, 5);
+        assert_eq!(candidate.total_copies, 4);
+        assert_eq!(candidate.chromosomes, vec!["test".to_string()]);
+        assert_eq!(candidate.positions.len(), 2);
+        assert_eq!(candidate.positions[1].start, 19);
+        assert_eq!(candidate.positions[1].chromosome_end, End::Left);
+
+        let mut tsv = Vec::new();
+        write_candidates(&candidates, "tsv", &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let rows: Vec<&str> = tsv.lines().collect();
+        assert_eq!(
+            rows[0],
+            "canonical_repeat_unit\tcount\tk\ttotal_copies\tchromosomes\tleft_chromosomes\tright_chromosomes\tboth_ends_chromosomes\torientation_consistency\tscore\tcall"
+        );
+        assert_eq!(
+            rows[1],
+            "AACCT\t4\t5\t4\t1\t1\t0\t0\t1.000\t0.568\tpossible"
+        );
+        assert!(rows[2].starts_with("AAATT\t2\t5\t2\t"));
+
+        let mut json = Vec::new();
+        write_candidates(&candidates, "json", &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["positions"][0]["chromosome_end"], "5'");
+        assert_eq!(json[0]["positions"][0]["copies"], 2);
+    }
+
+    #[test]
+    fn test_candidate_ends() {
+        let region = |id: &str| Region {
+            id: id.into(),
+            start: 0,
+            end: 1000,
+            length: 1000,
+        };
+        // C-rich at the 5' end and G-rich at the 3' end
+        let positions = RepeatPositions(vec![
+            RepeatPosition::new(&region("chr1"), 0, 60, "CCCTAA"),
+            RepeatPosition::new(&region("chr1"), 940, 1000, "TTAGGG"),
+            RepeatPosition::new(&region("chr2"), 0, 60, "AACCCT"),
+            // the wrong way round
+            RepeatPosition::new(&region("chr3"), 0, 120, "TTAGGG"),
+        ]);
+        let candidates = get_candidates(
+            vec![("AACCCT".into(), 0)],
+            &positions.make_canonical_groups(),
+            3,
+        );
+        let candidate = &candidates[0];
+
+        assert_eq!(candidate.left_chromosomes, 3);
+        assert_eq!(candidate.right_chromosomes, 1);
+        assert_eq!(candidate.both_ends_chromosomes, 1);
+        assert_eq!(candidate.positions[1].strand, Strand::Reverse);
+        assert!(!candidate.positions[3].expected_orientation);
+        assert_eq!(candidate.orientation_consistency, 0.6);
+        assert_eq!(candidate.scores.support, 1.0);
+        assert_eq!(candidate.call, Call::Possible);
+    }
+
+    #[test]
+    fn test_scores() {
+        assert_eq!(log_score(0.0, 1000.0), 0.0);
+        assert!((log_score(100.0, 1000.0) - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(log_score(1e6, 1000.0), 1.0);
+
+        let scores = Scores {
+            copy_number: 1.0,
+            support: 1.0,
+            end_specificity: 1.0,
+            strand_consistency: 1.0,
+            array_length: 1.0,
+        };
+        assert!((scores.total() - 1.0).abs() < 1e-9);
+        assert_eq!(call(scores.total()), Call::Likely);
+        assert_eq!(call(0.5), Call::Possible);
+        assert_eq!(call(0.1), Call::Unlikely);
     }
 }
@@ -1,20 +1,21 @@
-use crate::{clades, utils, SubCommand};
-use anyhow::{Context, Result};
-use bio::io::fasta;
-use std::fs::{create_dir_all, File};
-use std::io::LineWriter;
-use std::io::Write;
+use crate::search::{
+    get_approximate, get_step, write_records, ArrayOutput, WindowOptions, WindowOutput,
+};
+use crate::{clades, reader, taxonomy, utils, SubCommand};
+use anyhow::Result;
+use std::fs::create_dir_all;
 use std::path::PathBuf;
 use std::process;
-use std::str;
 
 /// The entry point for `tidk find`.
 ///
 /// Finder uses the clade specific telomere sequence and queries against the genome.
-pub fn finder(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
+pub fn finder(matches: &clap::ArgMatches) -> Result<()> {
+    // the built in table, or with the user's database
+    let clade_table = clades::CladeTable::from_matches(matches)?;
     // print table of telomeric sequences
     if matches.get_flag("print") {
-        clades::print_table();
+        clade_table.print();
         process::exit(1);
     }
 
@@ -22,36 +23,44 @@ pub fn finder(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
         .get_one::<PathBuf>("fasta")
         .expect("errored by clap")
         .clone();
-    let reader = fasta::Reader::from_file(input_fasta)?;
-
-    let clade = matches.get_one::<String>("clade").expect("errored by clap");
-    let clade_info = clades::return_telomere_sequence(clade);
+    // optionally only read some regions of an indexed fasta
+    let regions = reader::get_regions(matches);
+    let reference = reader::get_reference(matches);
+    let records = reader::sequence_reader(input_fasta, reference.as_deref(), regions.as_ref())?;
+
+    // the clade given, or the nearest one to a taxon in the taxonomy,
+    // which is only resolved once, here, and passed on to the log
+    let (clade, telomeric_repeats) = match taxonomy::resolve(matches, &clade_table)? {
+        Some(resolved) => {
+            resolved.report();
+            (resolved.to_string(), resolved.telomeric_repeats)
+        }
+        None => {
+            let clade = matches.get_one::<String>("clade").expect("errored by clap");
+            (clade.clone(), clade_table.get(clade)?.to_vec())
+        }
+    };
 
-    if clade_info.length == 1 {
+    if let [telomeric_repeat] = &telomeric_repeats[..] {
         eprintln!(
             "[+]\tSearching genome for a single telomeric repeat: {}",
-            clade_info
-                .seq
-                .get(0)
-                .context("Could not get the first element of `seq`.")?
+            telomeric_repeat
         );
-    } else if clade_info.length > 1 {
+    } else {
         eprintln!(
             "[+]\tSearching genome for {} telomeric repeats:",
-            clade_info.length
+            telomeric_repeats.len()
         );
-        for telomeric_repeat in 0..clade_info.length {
-            eprintln!(
-                "[+]\t\t{}",
-                clade_info.seq.get(telomeric_repeat).context(format!(
-                    "Could not get the {} element of `seq`.",
-                    telomeric_repeat
-                ))?
-            );
+        for telomeric_repeat in &telomeric_repeats {
+            eprintln!("[+]\t\t{}", telomeric_repeat);
         }
     }
 
     let window_size: usize = *matches.get_one::<usize>("window").expect("errored by clap");
+    let step = get_step(matches, window_size)?;
+    let extension = matches
+        .get_one::<String>("extension")
+        .expect("defaulted by clap");
     let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
     let output = matches
         .get_one::<PathBuf>("output")
@@ -60,120 +69,41 @@ pub fn finder(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     // create directory for output
     create_dir_all(outdir)?;
 
-    // create file
-    let file_name = format!(
-        "{}/{}{}",
-        outdir.display(),
-        output.display(),
-        "_telomeric_repeat_windows.tsv"
-    );
-    let finder_file = File::create(file_name)?;
-    let mut finder_file = LineWriter::new(finder_file);
-    // add headers
-    writeln!(
-        finder_file,
-        "id\twindow\tforward_repeat_number\treverse_repeat_number\ttelomeric_repeat"
+    // optionally count approximate matches too
+    let approximate = get_approximate(matches);
+
+    // all the telomeric repeats are searched for at once
+    let options = WindowOptions {
+        scanner: utils::MotifScanner::new(&telomeric_repeats)?,
+        telomeric_repeats,
+        window_size,
+        step,
+        approximate,
+    };
+
+    // create file(s), with headers
+    let mut finder_output = WindowOutput::create(outdir, output, extension, &options)?;
+    // and optionally the telomeric arrays
+    let mut finder_arrays = ArrayOutput::create(matches, outdir, output)?;
+
+    // iterate over the fasta records, or regions, with the
+    // window counter shared with `tidk search`
+    write_records(
+        records,
+        &mut finder_output,
+        finder_arrays.as_mut(),
+        &options,
     )?;
-
-    // extract the string from TelomereSeq struct
-    // dereference here because of Box<T>
-    let telomeric_repeat = *clade_info.seq.0;
-
-    // iterate over the fasta records
-    for result in reader.records() {
-        let record = result?;
-        let id = record.id().to_owned();
-
-        // fn window counter
-        write_window_counts(
-            record,
-            &mut finder_file,
-            clade_info.clone(),
-            telomeric_repeat,
-            window_size,
-            id.clone(),
-        )?;
-
-        eprintln!("[+]\tChromosome {} processed", id);
-    }
+    finder_output.finish()?;
     eprintln!("[+]\tFinished searching genome.");
 
     // optional log file
-    sc.log(matches)?;
-
-    Ok(())
-}
-
-/// Creates the window iterator and iterates over each iteration of the
-/// fasta file, writing on the fly.
-fn write_window_counts<T: std::io::Write>(
-    sequence: bio::io::fasta::Record,
-    file: &mut LineWriter<T>,
-    clade_info: clades::TelomereSeq,
-    telomeric_repeat: &[&str],
-    window_size: usize,
-    id: String,
-) -> Result<()> {
-    // needed as in some clades there is more than one telomeric repeat sequence
-    let mut telomeric_repeat_index = 0;
-    loop {
-        // break this loop if we reach the end of &[&str] of telomeric repeats
-        if clade_info.length == telomeric_repeat_index {
-            break;
-        }
-
-        // get forward and reverse sequences, and length
-        // to remove overlapping matches.
-        let forward_telomeric_seq =
-            *telomeric_repeat
-                .get(telomeric_repeat_index)
-                .context(format!(
-                    "Could not get the telomeric repeat with index: {}.",
-                    telomeric_repeat_index
-                ))?;
-        let reverse_telomeric_seq = utils::reverse_complement(forward_telomeric_seq);
-        let current_telomeric_length = forward_telomeric_seq.len();
-
-        // create the iterator in each loop iteration isnt costly is it?
-        let windows = sequence.seq().chunks(window_size);
-        // keep track of the window size
-
-        let mut end = window_size;
-        // iterate over windows
-        for (i, window) in windows.enumerate() {
-            // make window uppercase
-            let windows_upper = str::from_utf8(window)?.to_uppercase();
-            // for each window, find the motifs in this
-            let forward_motif = utils::find_motifs(forward_telomeric_seq, &windows_upper);
-            let reverse_motif = utils::find_motifs(&reverse_telomeric_seq, &windows_upper);
-
-            // remove overlapping matches
-            // not sure this is necessary, but thought it might be...
-            let forward_motif_noverlap =
-                utils::remove_overlapping_indexes(forward_motif, current_telomeric_length);
-            let reverse_motif_noverlap =
-                utils::remove_overlapping_indexes(reverse_motif, current_telomeric_length);
-
-            // the number of matches for forward/reverse
-            let forward_repeat_number = forward_motif_noverlap.len();
-            let reverse_repeat_number = reverse_motif_noverlap.len();
-
-            if i != 0 {
-                end += window_size;
-            }
-            if end > sequence.seq().len() {
-                end = sequence.seq().len();
-            }
-            // write to file
-            writeln!(
-                file,
-                "{}\t{}\t{}\t{}\t{}",
-                id, end, forward_repeat_number, reverse_repeat_number, forward_telomeric_seq
-            )?;
-        }
-        // go to the next telomeric repeat (if there is one)
-        telomeric_repeat_index += 1;
+    SubCommand::Find {
+        clade,
+        telomeric_repeats: options.telomeric_repeats,
     }
+    .log(matches)?;
+
     Ok(())
 }
 
@@ -182,8 +112,8 @@ mod tests {
     use std::io::{LineWriter, Read};
 
     use crate::clades::{Seq, TelomereSeq};
-
-    use super::write_window_counts;
+    use crate::search::{count_windows, write_counts, WindowOptions, WindowOutput, BLOCK_SIZE};
+    use crate::utils::MotifScanner;
 
     // a wrapper for making a bio::io::fasta record
     fn make_record(id: &str, seq: &[u8]) -> bio::io::fasta::Record {
@@ -193,11 +123,22 @@ mod tests {
     // take a record, write to a vector (fake file), then read out of this the output.
     fn calc_windows(rec: bio::io::fasta::Record, ts: TelomereSeq, ws: usize) -> String {
         let file = Vec::new();
-        let mut lw = LineWriter::new(file);
+        let mut output = WindowOutput::Tsv(LineWriter::new(file));
         let id = rec.id().to_owned();
 
-        let telomeric_repeat = *ts.seq.0;
-        write_window_counts(rec, &mut lw, ts, telomeric_repeat, ws, id).unwrap();
+        let telomeric_repeats: Vec<String> = ts.seq.0.iter().map(|e| e.to_string()).collect();
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&telomeric_repeats).unwrap(),
+            telomeric_repeats,
+            window_size: ws,
+            step: ws,
+            approximate: None,
+        };
+        let counts = count_windows(rec.seq(), &options, BLOCK_SIZE);
+        write_counts(&counts, &mut output, &options, &id, 0).unwrap();
+        let WindowOutput::Tsv(lw) = output else {
+            unreachable!()
+        };
 
         // read file contents to new vec
         let mut out = Vec::new();
@@ -227,10 +168,19 @@ mod tests {
         let rows: Vec<&str> = windows_calculation.lines().collect();
 
         // three in first window
-        assert_eq!(rows[0], "test1\t20\t2\t0\tAAACCCT");
+        assert_eq!(
+            rows[0],
+            "test1\t20\t2\t0\tAAACCCT\t0\t20\t14\t0.7000\t0.0000"
+        );
         // none in second
-        assert_eq!(rows[1], "test1\t40\t0\t0\tAAACCCT");
+        assert_eq!(
+            rows[1],
+            "test1\t40\t0\t0\tAAACCCT\t20\t20\t0\t0.0000\t0.0000"
+        );
         // two in third
-        assert_eq!(rows[2], "test1\t57\t1\t0\tAAACCCT");
+        assert_eq!(
+            rows[2],
+            "test1\t57\t1\t0\tAAACCCT\t40\t17\t7\t0.4118\t0.0000"
+        );
     }
 }
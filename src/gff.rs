@@ -0,0 +1,110 @@
+use crate::call::TelomericArray;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+
+/// The Sequence Ontology term for the features.
+const FEATURE_TYPE: &str = "telomeric_repeat";
+
+/// Writes telomeric arrays as GFF3 features, numbering them as it
+/// goes so each feature has a unique ID.
+pub struct Gff3Writer<T: Write> {
+    file: LineWriter<T>,
+    /// The number of features written so far.
+    features: usize,
+}
+
+impl Gff3Writer<File> {
+    /// Create the GFF3 file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(File::create(path)?)
+    }
+}
+
+impl<T: Write> Gff3Writer<T> {
+    /// A GFF3 writer, which writes the version pragma.
+    pub fn new(writer: T) -> Result<Self> {
+        let mut file = LineWriter::new(writer);
+        writeln!(file, "##gff-version 3")?;
+        Ok(Self { file, features: 0 })
+    }
+
+    /// Write an array of `telomeric_repeat` on `seqid`, shifting the array
+    /// by `offset` for when only a region of the sequence was read.
+    /// Coordinates are converted to GFF3's 1-based, inclusive ones.
+    pub fn write_array(
+        &mut self,
+        seqid: &str,
+        telomeric_repeat: &str,
+        array: &TelomericArray,
+        offset: usize,
+    ) -> Result<()> {
+        self.features += 1;
+        writeln!(
+            self.file,
+            "{}\ttidk\t{}\t{}\t{}\t.\t{}\t.\tID=telomeric_array_{};motif={};copy_number={};identity={:.3}",
+            escape(seqid),
+            FEATURE_TYPE,
+            array.start + offset + 1,
+            array.end + offset,
+            array.strand,
+            self.features,
+            telomeric_repeat,
+            array.copies,
+            array.purity
+        )?;
+        Ok(())
+    }
+
+    /// The underlying writer.
+    pub fn into_inner(self) -> Result<T> {
+        Ok(self.file.into_inner().map_err(|e| e.into_error())?)
+    }
+}
+
+/// Percent encode the characters which aren't allowed unescaped
+/// in a GFF3 seqid.
+fn escape(seqid: &str) -> String {
+    let mut escaped = String::with_capacity(seqid.len());
+    for c in seqid.chars() {
+        if c.is_ascii_alphanumeric() || ".:^*$@!+_?-|".contains(c) {
+            escaped.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::Strand;
+
+    #[test]
+    fn test_write_array() {
+        let array = TelomericArray {
+            start: 0,
+            end: 60,
+            strand: Strand::Reverse,
+            copies: 10,
+            purity: 1.0,
+        };
+        let mut gff = Gff3Writer::new(Vec::new()).unwrap();
+        gff.write_array("chr1", "TTAGGG", &array, 0).unwrap();
+        gff.write_array("chr 2", "TTAGGG", &array, 1000).unwrap();
+
+        let out = String::from_utf8(gff.into_inner().unwrap()).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows[0], "##gff-version 3");
+        assert_eq!(
+            rows[1],
+            "chr1\ttidk\ttelomeric_repeat\t1\t60\t.\t-\t.\tID=telomeric_array_1;motif=TTAGGG;copy_number=10;identity=1.000"
+        );
+        assert!(rows[2].starts_with("chr%202\ttidk\ttelomeric_repeat\t1001\t1060\t"));
+    }
+}
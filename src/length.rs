@@ -0,0 +1,311 @@
+use crate::reads::{self, ReadEnd, ReadTelomere};
+use crate::{call, reader, SubCommand};
+use anyhow::{bail, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str;
+
+/// The entry point for `tidk length`.
+///
+/// Estimate telomere length from reads. Only reads which start (or end)
+/// in a telomeric array, and continue into non-telomeric sequence, are
+/// used; if the array runs off the other end of the read, we don't know
+/// how long it really is.
+pub fn length(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
+    let input_reads = matches
+        .get_one::<PathBuf>("fasta")
+        .expect("errored by clap");
+    // reads mapped to a reference can be grouped by chromosome arm
+    let group_by_arm = matches.get_flag("group-by-arm");
+    let reference = reader::get_reference(matches);
+    let records = reader::grouped_records(input_reads, reference.as_deref(), group_by_arm)?;
+
+    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+    eprintln!(
+        "[+]\tEstimating telomere length for repeat(s): {}",
+        telomeric_repeats.join(", ")
+    );
+
+    let max_gap = *matches
+        .get_one::<usize>("max-gap")
+        .expect("defaulted by clap");
+    let min_copies = *matches
+        .get_one::<usize>("min-copies")
+        .expect("defaulted by clap");
+    let max_distance = *matches
+        .get_one::<usize>("max-distance")
+        .expect("defaulted by clap");
+    let min_flank = *matches
+        .get_one::<usize>("min-flank")
+        .expect("defaulted by clap");
+    let bootstraps = *matches
+        .get_one::<usize>("bootstraps")
+        .expect("defaulted by clap");
+    let confidence = *matches
+        .get_one::<f64>("confidence")
+        .expect("defaulted by clap");
+    if !(0.0..1.0).contains(&confidence) {
+        bail!("The confidence level must be between 0 and 1.")
+    }
+    let seed = *matches.get_one::<u64>("seed").expect("defaulted by clap");
+    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("errored by clap");
+
+    // create directory for output
+    create_dir_all(outdir)?;
+
+    let lengths_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomere_lengths.tsv"
+    );
+    let lengths_file = File::create(&lengths_name)?;
+    let mut lengths_file = LineWriter::new(lengths_file);
+    write!(
+        lengths_file,
+        "read_id\tread_length\tread_end\ttelomeric_repeat\ttelomere_length"
+    )?;
+    if group_by_arm {
+        write!(lengths_file, "\tgroup")?;
+    }
+    writeln!(lengths_file)?;
+
+    let mut lengths = Vec::new();
+    let mut group_lengths: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    // stream over the reads
+    for result in records {
+        let (group, record) = result?;
+        let sequence = str::from_utf8(record.seq())?.to_uppercase();
+
+        let telomeres = reads::read_telomeres(
+            &sequence,
+            &telomeric_repeats,
+            max_gap,
+            min_copies,
+            max_distance,
+        );
+        for telomere in telomeres
+            .iter()
+            .filter(|t| is_complete(t, sequence.len(), min_flank))
+        {
+            write!(
+                lengths_file,
+                "{}\t{}\t{}\t{}\t{}",
+                record.id(),
+                sequence.len(),
+                telomere.read_end,
+                telomere.telomeric_repeat,
+                telomere.array.len()
+            )?;
+            if let Some(group) = &group {
+                write!(lengths_file, "\t{}", group)?;
+                group_lengths
+                    .entry(group.clone())
+                    .or_default()
+                    .push(telomere.array.len());
+            }
+            writeln!(lengths_file)?;
+            lengths.push(telomere.array.len());
+        }
+    }
+    eprintln!(
+        "[+]\tFinished reading: {} telomere lengths from complete telomeric read ends.",
+        lengths.len()
+    );
+
+    // summarise the distribution
+    let summary_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomere_length_summary.tsv"
+    );
+    let summary_file = File::create(&summary_name)?;
+    let mut summary_file = LineWriter::new(summary_file);
+    writeln!(
+        summary_file,
+        "group\tn\tmean\tmean_ci_lower\tmean_ci_upper\tmedian\tmedian_ci_lower\tmedian_ci_upper"
+    )?;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    match LengthSummary::new(&lengths, bootstraps, confidence, &mut rng) {
+        Some(summary) => {
+            eprintln!(
+                "[+]\tMean telomere length: {:.1} ({:.1}-{:.1}), median: {:.1} ({:.1}-{:.1})",
+                summary.mean.0,
+                summary.mean.1,
+                summary.mean.2,
+                summary.median.0,
+                summary.median.1,
+                summary.median.2
+            );
+            write_summary(&mut summary_file, "all", &summary)?;
+        }
+        None => eprintln!("[-]\tNo complete telomeric read ends found."),
+    }
+    // and each group, if grouped
+    for (group, lengths) in &group_lengths {
+        if let Some(summary) = LengthSummary::new(lengths, bootstraps, confidence, &mut rng) {
+            write_summary(&mut summary_file, group, &summary)?;
+        }
+    }
+    eprintln!("[+]\tWritten to: {} and {}", lengths_name, summary_name);
+
+    // optional log file
+    sc.log(matches)?;
+
+    Ok(())
+}
+
+/// Whether a telomeric array at the end of a read gives us the full
+/// length of the telomere. The array must be on the strand we expect at
+/// that end, and be followed by at least `min_flank` bases of sequence
+/// which is not part of the array.
+pub fn is_complete(telomere: &ReadTelomere, read_length: usize, min_flank: usize) -> bool {
+    let flank = match telomere.read_end {
+        ReadEnd::Start => read_length - telomere.array.end,
+        ReadEnd::End => telomere.array.start,
+        // runs off both ends
+        ReadEnd::Both => return false,
+    };
+    telomere.expected_orientation && flank >= min_flank
+}
+
+/// The mean and median of the telomere lengths, each with the lower
+/// and upper bounds of their bootstrap confidence intervals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LengthSummary {
+    /// The number of telomere lengths.
+    pub n: usize,
+    /// The mean, and its confidence interval.
+    pub mean: (f64, f64, f64),
+    /// The median, and its confidence interval.
+    pub median: (f64, f64, f64),
+}
+
+impl LengthSummary {
+    /// Summarise some telomere lengths, with percentile bootstrap
+    /// confidence intervals. Returns `None` if there are no lengths.
+    pub fn new<R: Rng>(
+        lengths: &[usize],
+        bootstraps: usize,
+        confidence: f64,
+        rng: &mut R,
+    ) -> Option<Self> {
+        if lengths.is_empty() {
+            return None;
+        }
+        let lengths: Vec<f64> = lengths.iter().map(|l| *l as f64).collect();
+
+        let mut means = Vec::with_capacity(bootstraps);
+        let mut medians = Vec::with_capacity(bootstraps);
+        let mut resample = vec![0.0; lengths.len()];
+        for _ in 0..bootstraps {
+            for value in resample.iter_mut() {
+                *value = lengths[rng.gen_range(0..lengths.len())];
+            }
+            means.push(mean(&resample));
+            medians.push(median(&mut resample));
+        }
+
+        let alpha = (1.0 - confidence) / 2.0;
+        let interval = |mut stats: Vec<f64>, estimate: f64| {
+            if stats.is_empty() {
+                return (estimate, estimate, estimate);
+            }
+            stats.sort_by(|a, b| a.total_cmp(b));
+            (
+                estimate,
+                quantile(&stats, alpha),
+                quantile(&stats, 1.0 - alpha),
+            )
+        };
+
+        Some(Self {
+            n: lengths.len(),
+            mean: interval(means, mean(&lengths)),
+            median: interval(medians, median(&mut lengths.clone())),
+        })
+    }
+}
+
+/// The mean of some values.
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The median of some values, which are sorted in place. The two
+/// middle values are the same value if there are an odd number.
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    (values[(values.len() - 1) / 2] + values[values.len() / 2]) / 2.0
+}
+
+/// The nearest rank quantile of some sorted values.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Write a row of the summary TSV.
+fn write_summary<T: std::io::Write>(
+    file: &mut LineWriter<T>,
+    group: &str,
+    summary: &LengthSummary,
+) -> Result<()> {
+    writeln!(
+        file,
+        "{}\t{}\t{:.1}\t{:.1}\t{:.1}\t{:.1}\t{:.1}\t{:.1}",
+        group,
+        summary.n,
+        summary.mean.0,
+        summary.mean.1,
+        summary.mean.2,
+        summary.median.0,
+        summary.median.1,
+        summary.median.2
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_complete() {
+        let read = "CCTAACCTAACCTAACCTAAGCATGCATGCATGCATGCATGCAT";
+        let telomeres = reads::read_telomeres(read, &["TTAGG".into()], 0, 3, 5);
+        assert!(is_complete(&telomeres[0], read.len(), 20));
+        assert!(!is_complete(&telomeres[0], read.len(), 30));
+
+        // the whole read is telomeric
+        let read = "TTAGGTTAGGTTAGGTTAGG";
+        let telomeres = reads::read_telomeres(read, &["TTAGG".into()], 0, 3, 5);
+        assert!(!is_complete(&telomeres[0], read.len(), 0));
+    }
+
+    #[test]
+    fn test_length_summary() {
+        let lengths = [1000, 2000, 3000, 4000, 10000];
+        let mut rng = StdRng::seed_from_u64(42);
+        let summary = LengthSummary::new(&lengths, 1000, 0.95, &mut rng).unwrap();
+
+        assert_eq!(summary.n, 5);
+        assert_eq!(summary.mean.0, 4000.0);
+        assert_eq!(summary.median.0, 3000.0);
+        assert!(summary.mean.1 <= summary.mean.0 && summary.mean.0 <= summary.mean.2);
+        assert!(summary.median.1 >= 1000.0 && summary.median.2 <= 10000.0);
+
+        assert!(LengthSummary::new(&[], 1000, 0.95, &mut rng).is_none());
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+}
@@ -3,6 +3,11 @@ use chrono::Local;
 use clap::crate_version;
 use std::{io::Write, path::PathBuf};
 
+/// Writing BigWig files of the window counts from
+/// `tidk search` and `tidk find`.
+pub mod bigwig;
+/// The entry point for the `tidk call` subcommand.
+pub mod call;
 /// A module where the clades are defined, and their
 /// respective telomeric repeats are enumerated.
 pub mod clades;
@@ -10,19 +15,43 @@ pub mod clades;
 pub mod explore;
 /// The entry point for the `tidk find` subcommand.
 pub mod finder;
+/// Writing telomeric arrays as GFF3.
+pub mod gff;
+/// The entry point for the `tidk length` subcommand.
+pub mod length;
 /// Functions to plot output from `tidk search` and
 /// `tidk find`.
 pub mod plot;
+/// Reading FASTA and FASTQ input, which may be compressed,
+/// or come from stdin.
+pub mod reader;
+/// The entry point for the `tidk reads` subcommand.
+pub mod reads;
 /// The entry point for the `tidk search` subcommand.
 pub mod search;
+/// The entry point for the `tidk summary` subcommand.
+pub mod summary;
+/// Finding the clade of a species or taxid in a
+/// local copy of the NCBI taxonomy.
+pub mod taxonomy;
 /// Module for utilities.
 pub mod utils;
 
-/// Three possible subcommands.
+/// The subcommands which can write a log file.
 pub enum SubCommand {
-    Find,
+    /// `tidk find`, with the clade it searched for, which may have been
+    /// resolved from a taxon, and its telomeric repeats, so neither is
+    /// looked up again for the log.
+    Find {
+        clade: String,
+        telomeric_repeats: Vec<String>,
+    },
     Explore,
     Search,
+    Call,
+    Summary,
+    Reads,
+    Length,
 }
 
 /// A date format.
@@ -36,7 +65,10 @@ impl SubCommand {
         // only if log CLI arg is present
         if matches.get_flag("log") {
             match self {
-                SubCommand::Find => {
+                SubCommand::Find {
+                    clade,
+                    telomeric_repeats,
+                } => {
                     let output = matches
                         .get_one::<PathBuf>("output")
                         .expect("errored by clap");
@@ -44,16 +76,16 @@ impl SubCommand {
                     let input_fasta = matches
                         .get_one::<PathBuf>("fasta")
                         .expect("errored by clap");
-                    let clade = matches.get_one::<String>("clade").expect("errored by clap");
-                    let clade_info = clades::return_telomere_sequence(clade);
                     let window_size = *matches.get_one::<usize>("window").expect("errored by clap");
+                    let step = search::get_step(matches, window_size)?;
+                    let approximate = search::get_approximate(matches);
 
-                    let file_name = format!(
-                        "{}/{}{}",
-                        outdir.display(),
-                        output.display(),
-                        "_telomeric_repeat_windows.csv"
-                    );
+                    let extension = matches
+                        .get_one::<String>("extension")
+                        .expect("defaulted by clap");
+                    let file_name =
+                        search::window_file_names(outdir, output, extension, telomeric_repeats)
+                            .join(", ");
 
                     let log_string = format!(
                         r#"tidk version: {}
@@ -62,15 +94,25 @@ Date: {}
 `tidk find` was run with the following parameters:
     Input fasta: {}
     Window size: {}
+    Window step: {}
     Clade chosen: {}
-    Telomeric repeats queried: {}"#,
+    Clade database: {}
+    Telomeric repeats queried: {}
+    Approximate matching: {}
+    Regions read: {}
+    Telomeric arrays (GFF3): {}"#,
                         crate_version!(),
                         file_name,
                         Local::now().format(DATE_FORMAT_STR),
                         input_fasta.display(),
                         window_size,
+                        step,
                         clade,
-                        clade_info.seq.0.join(", ")
+                        format_db(matches),
+                        telomeric_repeats.join(", "),
+                        format_approximate(approximate),
+                        format_regions(reader::get_regions(matches)),
+                        format_arrays(matches)
                     );
 
                     // create file
@@ -95,10 +137,17 @@ Date: {}
                     let threshold = matches.get_one::<i32>("threshold");
 
                     let dist_from_chromosome_end = matches.get_one::<f64>("distance");
+                    let format = matches
+                        .get_one::<String>("format")
+                        .expect("defaulted by clap");
+                    let output = match matches.get_one::<PathBuf>("output") {
+                        Some(path) => format!("{} ({})", path.display(), format),
+                        None => format!("printed to STDOUT ({})", format),
+                    };
 
                     let log_string = format!(
                         r#"tidk version: {}
-Log information for output files: printed to STDOUT
+Log information for output files: {}
 Date: {}
 `tidk explore` was run with the following parameters:
     Input fasta: {}
@@ -106,8 +155,10 @@ Date: {}
     Or from length: {}
     To length: {}
     Threshold: {}
-    Searching at {}% distance from chromosome end"#,
+    Searching at {}% distance from chromosome end
+    Regions read: {}"#,
                         crate_version!(),
+                        output,
                         Local::now().format(DATE_FORMAT_STR),
                         input_fasta.display(),
                         {
@@ -135,6 +186,7 @@ Date: {}
                         threshold.unwrap(),
                         // safely unwrap
                         *dist_from_chromosome_end.unwrap() * 100.0,
+                        format_regions(reader::get_regions(matches)),
                     );
 
                     // create file
@@ -150,43 +202,99 @@ Date: {}
                     let input_fasta = matches
                         .get_one::<PathBuf>("fasta")
                         .expect("errored by clap");
-                    let telomeric_repeat = matches
-                        .get_one::<String>("string")
-                        .expect("errored by clap");
+                    let telomeric_repeats = search::get_search_strings(matches)?;
                     let extension = matches
                         .get_one::<String>("extension")
                         .expect("errored by clap");
 
-                    let window_size = matches.get_one::<usize>("window").expect("errored by clap");
+                    let window_size = *matches.get_one::<usize>("window").expect("errored by clap");
+                    let step = search::get_step(matches, window_size)?;
+                    let approximate = search::get_approximate(matches);
                     let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
                     let output = matches
                         .get_one::<PathBuf>("output")
                         .expect("errored by clap");
 
+                    let file_name =
+                        search::window_file_names(outdir, output, extension, &telomeric_repeats)
+                            .join(", ");
+
+                    let log_string = format!(
+                        r#"tidk version: {}
+Log information for output file: {}
+Date: {}
+`tidk search` was run with the following parameters:
+    Input fasta: {}
+    Telomeric repeat search string(s): {}
+    Window size: {}
+    Window step: {}
+    Approximate matching: {}
+    Regions read: {}
+    Telomeric arrays (GFF3): {}
+                    "#,
+                        crate_version!(),
+                        file_name,
+                        Local::now().format(DATE_FORMAT_STR),
+                        input_fasta.display(),
+                        telomeric_repeats.join(", "),
+                        window_size,
+                        step,
+                        format_approximate(approximate),
+                        format_regions(reader::get_regions(matches)),
+                        format_arrays(matches)
+                    );
+
                     // create file
+                    let log_file_name =
+                        format!("{}/{}{}", outdir.display(), output.display(), ".log");
+                    let log_file = std::fs::File::create(&log_file_name)?;
+                    let mut log_file = std::io::LineWriter::new(log_file);
+
+                    writeln!(log_file, "{}", log_string)?;
+
+                    Ok(eprintln!("[+]\tLog file written to: {}", log_file_name))
+                }
+                SubCommand::Call => {
+                    let input_fasta = matches
+                        .get_one::<PathBuf>("fasta")
+                        .expect("errored by clap");
+                    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+                    let max_gap = matches
+                        .get_one::<usize>("max-gap")
+                        .expect("errored by clap");
+                    let min_copies = matches
+                        .get_one::<usize>("min-copies")
+                        .expect("errored by clap");
+                    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+                    let output = matches
+                        .get_one::<PathBuf>("output")
+                        .expect("errored by clap");
+
                     let file_name = format!(
-                        "{}/{}{}{}",
+                        "{}/{}{}",
                         outdir.display(),
                         output.display(),
-                        "_telomeric_repeat_windows.",
-                        extension
+                        "_telomeric_arrays.bed"
                     );
 
                     let log_string = format!(
                         r#"tidk version: {}
 Log information for output file: {}
 Date: {}
-`tidk search` was run with the following parameters:
+`tidk call` was run with the following parameters:
     Input fasta: {}
-    Telomeric repeat search string: {}
-    Window size: {}
-                    "#,
+    Telomeric repeats queried: {}
+    Maximum gap: {}
+    Minimum copies: {}
+    GFF3 output: {}"#,
                         crate_version!(),
                         file_name,
                         Local::now().format(DATE_FORMAT_STR),
                         input_fasta.display(),
-                        telomeric_repeat,
-                        window_size
+                        telomeric_repeats.join(", "),
+                        max_gap,
+                        min_copies,
+                        matches.get_flag("gff3")
                     );
 
                     // create file
@@ -197,7 +305,206 @@ Date: {}
 
                     writeln!(log_file, "{}", log_string)?;
 
-                    Ok(eprintln!("[+]\tLog file written to: {}", log_file_name))
+                    eprintln!("[+]\tLog file written to: {}", log_file_name);
+                    Ok(())
+                }
+                SubCommand::Summary => {
+                    let input_fasta = matches
+                        .get_one::<PathBuf>("fasta")
+                        .expect("errored by clap");
+                    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+                    let max_gap = matches
+                        .get_one::<usize>("max-gap")
+                        .expect("errored by clap");
+                    let min_copies = matches
+                        .get_one::<usize>("min-copies")
+                        .expect("errored by clap");
+                    let max_distance = matches
+                        .get_one::<usize>("max-distance")
+                        .expect("errored by clap");
+                    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+                    let output = matches
+                        .get_one::<PathBuf>("output")
+                        .expect("errored by clap");
+
+                    let file_name = format!(
+                        "{}/{}{}",
+                        outdir.display(),
+                        output.display(),
+                        "_telomere_summary.{tsv,json}"
+                    );
+
+                    let log_string = format!(
+                        r#"tidk version: {}
+Log information for output files: {}
+Date: {}
+`tidk summary` was run with the following parameters:
+    Input fasta: {}
+    Telomeric repeats queried: {}
+    Maximum gap: {}
+    Minimum copies: {}
+    Maximum distance from sequence end: {}"#,
+                        crate_version!(),
+                        file_name,
+                        Local::now().format(DATE_FORMAT_STR),
+                        input_fasta.display(),
+                        telomeric_repeats.join(", "),
+                        max_gap,
+                        min_copies,
+                        max_distance
+                    );
+
+                    // create file
+                    let log_file_name =
+                        format!("{}/{}{}", outdir.display(), output.display(), ".log");
+                    let log_file = std::fs::File::create(&log_file_name)?;
+                    let mut log_file = std::io::LineWriter::new(log_file);
+
+                    writeln!(log_file, "{}", log_string)?;
+
+                    eprintln!("[+]\tLog file written to: {}", log_file_name);
+                    Ok(())
+                }
+                SubCommand::Reads => {
+                    let input_fasta = matches
+                        .get_one::<PathBuf>("fasta")
+                        .expect("errored by clap");
+                    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+                    let max_gap = matches
+                        .get_one::<usize>("max-gap")
+                        .expect("errored by clap");
+                    let min_copies = matches
+                        .get_one::<usize>("min-copies")
+                        .expect("errored by clap");
+                    let max_distance = matches
+                        .get_one::<usize>("max-distance")
+                        .expect("errored by clap");
+                    let bin_size = matches
+                        .get_one::<usize>("bin-size")
+                        .expect("errored by clap");
+                    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+                    let output = matches
+                        .get_one::<PathBuf>("output")
+                        .expect("errored by clap");
+
+                    let file_name = format!(
+                        "{}/{}{}",
+                        outdir.display(),
+                        output.display(),
+                        "_{telomeric_reads,telomere_length_histogram}.tsv"
+                    );
+
+                    let log_string = format!(
+                        r#"tidk version: {}
+Log information for output files: {}
+Date: {}
+`tidk reads` was run with the following parameters:
+    Input reads: {}
+    Telomeric repeats queried: {}
+    Maximum gap: {}
+    Minimum copies: {}
+    Maximum distance from read end: {}
+    Histogram bin size: {}
+    Grouped by chromosome arm: {}"#,
+                        crate_version!(),
+                        file_name,
+                        Local::now().format(DATE_FORMAT_STR),
+                        input_fasta.display(),
+                        telomeric_repeats.join(", "),
+                        max_gap,
+                        min_copies,
+                        max_distance,
+                        bin_size,
+                        matches.get_flag("group-by-arm")
+                    );
+
+                    // create file
+                    let log_file_name =
+                        format!("{}/{}{}", outdir.display(), output.display(), ".log");
+                    let log_file = std::fs::File::create(&log_file_name)?;
+                    let mut log_file = std::io::LineWriter::new(log_file);
+
+                    writeln!(log_file, "{}", log_string)?;
+
+                    eprintln!("[+]\tLog file written to: {}", log_file_name);
+                    Ok(())
+                }
+                SubCommand::Length => {
+                    let input_fasta = matches
+                        .get_one::<PathBuf>("fasta")
+                        .expect("errored by clap");
+                    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+                    let max_gap = matches
+                        .get_one::<usize>("max-gap")
+                        .expect("errored by clap");
+                    let min_copies = matches
+                        .get_one::<usize>("min-copies")
+                        .expect("errored by clap");
+                    let max_distance = matches
+                        .get_one::<usize>("max-distance")
+                        .expect("errored by clap");
+                    let min_flank = matches
+                        .get_one::<usize>("min-flank")
+                        .expect("errored by clap");
+                    let bootstraps = matches
+                        .get_one::<usize>("bootstraps")
+                        .expect("errored by clap");
+                    let confidence = matches
+                        .get_one::<f64>("confidence")
+                        .expect("errored by clap");
+                    let seed = matches.get_one::<u64>("seed").expect("errored by clap");
+                    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+                    let output = matches
+                        .get_one::<PathBuf>("output")
+                        .expect("errored by clap");
+
+                    let file_name = format!(
+                        "{}/{}{}",
+                        outdir.display(),
+                        output.display(),
+                        "_telomere_length{s,_summary}.tsv"
+                    );
+
+                    let log_string = format!(
+                        r#"tidk version: {}
+Log information for output files: {}
+Date: {}
+`tidk length` was run with the following parameters:
+    Input reads: {}
+    Telomeric repeats queried: {}
+    Maximum gap: {}
+    Minimum copies: {}
+    Maximum distance from read end: {}
+    Minimum flank: {}
+    Bootstraps: {}
+    Confidence level: {}
+    Seed: {}
+    Grouped by chromosome arm: {}"#,
+                        crate_version!(),
+                        file_name,
+                        Local::now().format(DATE_FORMAT_STR),
+                        input_fasta.display(),
+                        telomeric_repeats.join(", "),
+                        max_gap,
+                        min_copies,
+                        max_distance,
+                        min_flank,
+                        bootstraps,
+                        confidence,
+                        seed,
+                        matches.get_flag("group-by-arm")
+                    );
+
+                    // create file
+                    let log_file_name =
+                        format!("{}/{}{}", outdir.display(), output.display(), ".log");
+                    let log_file = std::fs::File::create(&log_file_name)?;
+                    let mut log_file = std::io::LineWriter::new(log_file);
+
+                    writeln!(log_file, "{}", log_string)?;
+
+                    eprintln!("[+]\tLog file written to: {}", log_file_name);
+                    Ok(())
                 }
             }
         } else {
@@ -205,3 +512,48 @@ Date: {}
         }
     }
 }
+
+/// Describe the approximate matching for the log files.
+fn format_approximate(approximate: Option<utils::Approximate>) -> String {
+    match approximate {
+        Some(utils::Approximate::Mismatches(k)) => format!("up to {} mismatches", k),
+        Some(utils::Approximate::EditDistance(k)) => format!("up to edit distance {}", k),
+        None => "None".into(),
+    }
+}
+
+/// Describe the clade database given with `--db`, for the log files.
+fn format_db(matches: &clap::ArgMatches) -> String {
+    match matches.get_one::<PathBuf>("db") {
+        Some(db) if matches.get_flag("replace-db") => {
+            format!("{}, replacing the built in table", db.display())
+        }
+        Some(db) => format!("{}, added to the built in table", db.display()),
+        None => "Built in".into(),
+    }
+}
+
+/// Describe the telomeric arrays written with `--gff3`, for the log files.
+fn format_arrays(matches: &clap::ArgMatches) -> String {
+    if !matches.get_flag("gff3") {
+        return "None".into();
+    }
+    format!(
+        "maximum gap {}, minimum copies {}",
+        matches
+            .get_one::<usize>("max-gap")
+            .expect("defaulted by clap"),
+        matches
+            .get_one::<usize>("min-copies")
+            .expect("defaulted by clap")
+    )
+}
+
+/// Describe the regions read for the log files.
+fn format_regions(regions: Option<reader::Regions>) -> String {
+    match regions {
+        Some(reader::Regions::Ends(ends)) => format!("{} bases from either end", ends),
+        Some(reader::Regions::Bed(bed)) => format!("from {}", bed.display()),
+        None => "All".into(),
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
-use clap::{arg, builder::ArgPredicate, crate_version, value_parser, Arg, Command};
+use clap::{arg, builder::ArgPredicate, crate_version, value_parser, Arg, ArgGroup, Command};
 use std::path::PathBuf;
-use tidk::{clades::CLADES, explore, finder, plot, search, SubCommand};
+use tidk::{
+    call, clades::CLADES, explore, finder, length, plot, reads, search, summary,
+    utils::validate_dna_string, SubCommand,
+};
 
 fn main() -> Result<()> {
     // command line options
@@ -11,6 +14,12 @@ fn main() -> Result<()> {
         .arg_required_else_help(true)
         .author("Max Brown <euphrasiamax@gmail.com>")
         .about("A Telomere Identification Toolkit.")
+        .arg(
+            arg!(--threads <THREADS> "The number of threads to use, by default one per core")
+                .value_parser(value_parser!(usize))
+                .global(true)
+                .display_order(100)
+        )
         .subcommand(
             Command::new("find")
                 .about("Supply the name of a clade your organsim belongs to, and this submodule will find all telomeric repeat matches for that clade.")
@@ -18,9 +27,13 @@ fn main() -> Result<()> {
                     Arg::new("fasta")
                         .value_name("FASTA")
                         .value_parser(value_parser!(PathBuf))
-                        .help("The input fasta file")
+                        .help("The input fasta or fastq file, which may be gzip or bgzip compressed (use - to read from stdin), or sam, bam or cram")
                         .required_unless_present("print")
                 )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
                 .arg(
                     // no longer required.
                     arg!(-w --window [WINDOW] "Window size to calculate telomeric repeat counts in")
@@ -28,9 +41,59 @@ fn main() -> Result<()> {
                         .default_value("10000")
                 )
                 .arg(
-                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in")
-                        .required_unless_present("print")
-                        .value_parser(CLADES.to_owned())
+                    arg!(--step [STEP] "Distance between the starts of windows, so windows overlap if less than the window size [default: window size]")
+                        .value_parser(value_parser!(usize))
+                )
+                .arg(
+                    arg!(--mismatches [MISMATCHES] "Also count approximate matches with up to this many mismatches, as extra columns")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("edit-distance")
+                )
+                .arg(
+                    arg!(--"edit-distance" [EDIT_DISTANCE] "Also count approximate matches with up to this edit distance, as extra columns")
+                        .value_parser(value_parser!(usize))
+                )
+                .arg(
+                    arg!(--gff3 "Also merge the repeat matches into telomeric arrays, as in tidk call, and write them as GFF3")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array, with --gff3")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported, with --gff3")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .arg(
+                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in (see --print for the clades)")
+                        .required_unless_present_any(["print", "taxid", "species"])
+                        .conflicts_with_all(["taxid", "species"])
+                )
+                .arg(
+                    arg!(--taxid <TAXID> "Use the nearest clade in the lineage of this NCBI taxid instead, from --taxdump")
+                        .value_parser(value_parser!(u32))
+                        .conflicts_with("species")
+                        .requires("taxdump")
+                )
+                .arg(
+                    arg!(--species <SPECIES> "Use the nearest clade in the lineage of this species (or other taxon) instead, from --taxdump")
+                        .requires("taxdump")
+                )
+                .arg(
+                    arg!(--taxdump <DIR> "A directory with the names.dmp and nodes.dmp of the NCBI taxonomy, from taxdump.tar.gz")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--db <DB> "A CSV of telomeric repeats by clade, in the format of clades/curated.csv, which is added to the built in table")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--"replace-db" "Only use the clades in --db, rather than adding them to the built in table")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("db")
                 )
                 .arg(
                     arg!(-o --output <OUTPUT> "Output filename for the TSVs (without extension)")
@@ -42,10 +105,24 @@ fn main() -> Result<()> {
                         .required_unless_present("print")
                         .value_parser(value_parser!(PathBuf))
                 )
+                .arg(
+                    arg!(-e --extension [EXTENSION] "The extension, defining the output type of the file")
+                        .default_value("tsv")
+                        .value_parser(["tsv", "bedgraph", "bigwig"])
+                )
                 .arg(
                     arg!(-p --print "Print a table of clades, along with their telomeric sequences")
                         .action(clap::ArgAction::SetTrue)
                 )
+                .arg(
+                    arg!(--ends <ENDS> "Only read this many bases from either end of each sequence. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("regions")
+                )
+                .arg(
+                    arg!(--regions <BED> "Only read the regions in this BED file. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(PathBuf))
+                )
                 .arg(
                     arg!(--log "Output a log file")
                         .action(clap::ArgAction::SetTrue)
@@ -59,7 +136,11 @@ fn main() -> Result<()> {
                         .value_name("FASTA")
                         .value_parser(value_parser!(PathBuf))
                         .required(true)
-                        .help("The input fasta file")
+                        .help("The input fasta or fastq file, which may be gzip or bgzip compressed (use - to read from stdin), or sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
                 )
                 .arg(
                     arg!(-l --length [LENGTH] "Length of substring")
@@ -93,6 +174,24 @@ fn main() -> Result<()> {
                     arg!(-v --verbose "Print verbose output.")
                         .action(clap::ArgAction::SetTrue)
                 )
+                .arg(
+                    arg!(-f --format [FORMAT] "The output format. JSON includes the chromosomes, ends and positions of each potential telomeric repeat")
+                        .value_parser(["tsv", "json"])
+                        .default_value("tsv")
+                )
+                .arg(
+                    arg!(-o --output [OUTPUT] "Write the output to this file, instead of STDOUT")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--ends <ENDS> "Only read this many bases from either end of each sequence. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("regions")
+                )
+                .arg(
+                    arg!(--regions <BED> "Only read the regions in this BED file. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(PathBuf))
+                )
                 .arg(
                     arg!(--log "Output a log file.")
                         .action(clap::ArgAction::SetTrue)
@@ -106,10 +205,25 @@ fn main() -> Result<()> {
                         .value_name("FASTA")
                         .value_parser(value_parser!(PathBuf))
                         .required(true)
-                        .help("The input fasta file")
+                        .help("The input fasta or fastq file, which may be gzip or bgzip compressed (use - to read from stdin), or sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-s --string <STRING> "The DNA string to query the genome with, which may contain IUPAC codes. Can be given more than once")
+                        .value_parser(validate_dna_string)
+                        .action(clap::ArgAction::Append)
                 )
                 .arg(
-                    arg!(-s --string <STRING> "The DNA string to query the genome with")
+                    arg!(--"string-file" <STRING_FILE> "A file of DNA strings to query the genome with, one per line")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .group(
+                    ArgGroup::new("strings")
+                        .args(["string", "string-file"])
+                        .multiple(true)
                         .required(true)
                 )
                 .arg(
@@ -117,8 +231,36 @@ fn main() -> Result<()> {
                         .value_parser(value_parser!(usize))
                         .default_value("10000")
                 )
+                .arg(
+                    arg!(--step [STEP] "Distance between the starts of windows, so windows overlap if less than the window size [default: window size]")
+                        .value_parser(value_parser!(usize))
+                )
+                .arg(
+                    arg!(--mismatches [MISMATCHES] "Also count approximate matches with up to this many mismatches, as extra columns")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("edit-distance")
+                )
+                .arg(
+                    arg!(--"edit-distance" [EDIT_DISTANCE] "Also count approximate matches with up to this edit distance, as extra columns")
+                        .value_parser(value_parser!(usize))
+                )
+                .arg(
+                    arg!(--gff3 "Also merge the repeat matches into telomeric arrays, as in tidk call, and write them as GFF3")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array, with --gff3")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported, with --gff3")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
                 .arg(
                     arg!(-o --output <OUTPUT> "Output filename for the TSVs (without extension)")
+                        .value_parser(value_parser!(PathBuf))
                         .required(true)
                 )
                 .arg(
@@ -129,7 +271,276 @@ fn main() -> Result<()> {
                 .arg(
                     arg!(-e --extension [EXTENSION] "The extension, defining the output type of the file")
                         .default_value("tsv")
-                        .value_parser(["tsv", "bedgraph"])
+                        .value_parser(["tsv", "bedgraph", "bigwig"])
+                )
+                .arg(
+                    arg!(--ends <ENDS> "Only read this many bases from either end of each sequence. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("regions")
+                )
+                .arg(
+                    arg!(--regions <BED> "Only read the regions in this BED file. Needs an uncompressed or bgzipped, faidx indexed fasta")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--log "Output a log file")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("call")
+                .about("Merge consecutive telomeric repeat matches into arrays, and write these as BED.")
+                .arg(
+                    Arg::new("fasta")
+                        .value_name("FASTA")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The input fasta or fastq file, which may be gzip or bgzip compressed (use - to read from stdin), or sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-s --string <STRING> "The DNA string to query the genome with, which may contain IUPAC codes")
+                        .value_parser(validate_dna_string)
+                )
+                .arg(
+                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in")
+                        .value_parser(CLADES.to_owned())
+                )
+                .group(
+                    ArgGroup::new("repeat")
+                        .args(["string", "clade"])
+                        .required(true)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output filename for the BED and GFF3 (without extension)")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                )
+                .arg(
+                    arg!(--gff3 "Also write the telomeric arrays as GFF3")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(-d --dir <DIR> "Output directory to write files to")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--log "Output a log file")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("summary")
+                .about("Report, for each sequence, whether there are telomeric arrays at either end (i.e. is it telomere-to-telomere).")
+                .arg(
+                    Arg::new("fasta")
+                        .value_name("FASTA")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The input fasta or fastq file, which may be gzip or bgzip compressed (use - to read from stdin), or sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-s --string <STRING> "The DNA string to query the genome with, which may contain IUPAC codes")
+                        .value_parser(validate_dna_string)
+                )
+                .arg(
+                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in")
+                        .value_parser(CLADES.to_owned())
+                )
+                .group(
+                    ArgGroup::new("repeat")
+                        .args(["string", "clade"])
+                        .required(true)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .arg(
+                    arg!(--"max-distance" [MAX_DISTANCE] "Maximum distance (bp) of an array from the end of a sequence to count as a telomere")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1000")
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output filename for the TSV and JSON (without extension)")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                )
+                .arg(
+                    arg!(-d --dir <DIR> "Output directory to write files to")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--log "Output a log file")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("reads")
+                .about("Find telomeric arrays at the ends of (long) reads, and make a histogram of their lengths.")
+                .arg(
+                    Arg::new("fasta")
+                        .value_name("READS")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The input reads as fasta, fastq (which may be gzip or bgzip compressed, use - to read from stdin), sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-s --string <STRING> "The DNA string to query the genome with, which may contain IUPAC codes")
+                        .value_parser(validate_dna_string)
+                )
+                .arg(
+                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in")
+                        .value_parser(CLADES.to_owned())
+                )
+                .group(
+                    ArgGroup::new("repeat")
+                        .args(["string", "clade"])
+                        .required(true)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .arg(
+                    arg!(--"max-distance" [MAX_DISTANCE] "Maximum distance (bp) of an array from the end of a read to count as a telomere")
+                        .value_parser(value_parser!(usize))
+                        .default_value("100")
+                )
+                .arg(
+                    arg!(--"bin-size" [BIN_SIZE] "Bin size (bp) of the telomere length histogram")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1000")
+                )
+                .arg(
+                    arg!(--"group-by-arm" "Group the reads by the chromosome arm they are mapped to. Needs SAM, BAM or CRAM input")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output filename for the TSVs (without extension)")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                )
+                .arg(
+                    arg!(-d --dir <DIR> "Output directory to write files to")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--log "Output a log file")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("length")
+                .about("Estimate telomere length from reads which start or end in a complete telomeric array, with bootstrap confidence intervals.")
+                .arg(
+                    Arg::new("fasta")
+                        .value_name("READS")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("The input reads as fasta, fastq (which may be gzip or bgzip compressed, use - to read from stdin), sam, bam or cram")
+                )
+                .arg(
+                    arg!(--reference <REFERENCE> "The reference fasta to decode a cram with, if htslib can't find it from the cram header")
+                        .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-s --string <STRING> "The DNA string to query the genome with, which may contain IUPAC codes")
+                        .value_parser(validate_dna_string)
+                )
+                .arg(
+                    arg!(-c --clade <CLADE> "The clade of organism to identify telomeres in")
+                        .value_parser(CLADES.to_owned())
+                )
+                .group(
+                    ArgGroup::new("repeat")
+                        .args(["string", "clade"])
+                        .required(true)
+                )
+                .arg(
+                    arg!(--"max-gap" [MAX_GAP] "Maximum gap (bp) between consecutive repeat matches within an array")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                )
+                .arg(
+                    arg!(--"min-copies" [MIN_COPIES] "Minimum number of repeat copies for an array to be reported")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .arg(
+                    arg!(--"max-distance" [MAX_DISTANCE] "Maximum distance (bp) of an array from the end of a read to count as a telomere")
+                        .value_parser(value_parser!(usize))
+                        .default_value("100")
+                )
+                .arg(
+                    arg!(--"min-flank" [MIN_FLANK] "Minimum length (bp) of non-telomeric sequence after an array, so it's not truncated by the read")
+                        .value_parser(value_parser!(usize))
+                        .default_value("500")
+                )
+                .arg(
+                    arg!(--bootstraps [BOOTSTRAPS] "Number of bootstrap resamples for the confidence intervals")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1000")
+                )
+                .arg(
+                    arg!(--confidence [CONFIDENCE] "Confidence level of the intervals")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.95")
+                )
+                .arg(
+                    arg!(--seed [SEED] "Seed for the random number generator, for reproducible intervals")
+                        .value_parser(value_parser!(u64))
+                        .default_value("42")
+                )
+                .arg(
+                    arg!(--"group-by-arm" "Group the reads by the chromosome arm they are mapped to. Needs SAM, BAM or CRAM input")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output filename for the TSVs (without extension)")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                )
+                .arg(
+                    arg!(-d --dir <DIR> "Output directory to write files to")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
                 )
                 .arg(
                     arg!(--log "Output a log file")
@@ -160,13 +571,23 @@ fn main() -> Result<()> {
                         .value_parser(value_parser!(PathBuf))
                         .default_value("tidk-plot")
                 )
+                .arg(
+                    arg!(--density "Plot the percentage of each window covered by repeats, rather than the number of repeats")
+                        .action(clap::ArgAction::SetTrue)
+                )
         )
         .get_matches();
 
+    if let Some(threads) = matches.get_one::<usize>("threads") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(*threads)
+            .build_global()?;
+    }
+
     // feed command line options to each main function
     match matches.subcommand() {
         Some(("find", matches)) => {
-            finder::finder(matches, SubCommand::Find)?;
+            finder::finder(matches)?;
         }
         Some(("explore", matches)) => {
             explore::explore(matches, SubCommand::Explore)?;
@@ -174,6 +595,18 @@ fn main() -> Result<()> {
         Some(("search", matches)) => {
             search::search(matches, SubCommand::Search)?;
         }
+        Some(("call", matches)) => {
+            call::call(matches, SubCommand::Call)?;
+        }
+        Some(("summary", matches)) => {
+            summary::summary(matches, SubCommand::Summary)?;
+        }
+        Some(("reads", matches)) => {
+            reads::reads(matches, SubCommand::Reads)?;
+        }
+        Some(("length", matches)) => {
+            length::length(matches, SubCommand::Length)?;
+        }
         Some(("plot", matches)) => {
             plot::plot(matches)?;
         }
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use csv::ReaderBuilder;
 use serde::Deserialize;
 use std::fs::File;
@@ -19,9 +19,13 @@ pub fn plot(matches: &clap::ArgMatches) -> Result<()> {
     let output = matches
         .get_one::<PathBuf>("output")
         .expect("defaulted by clap");
+    let density = matches.get_flag("density");
 
     // parse the tsv
     let parsed_tsv = parse_tsv(tsv.to_path_buf())?;
+    if density && parsed_tsv.iter().any(|e| e.repeat_fraction.is_none()) {
+        bail!("The TSV has no `repeat_fraction` column, so can't plot density. Re-run `tidk search` or `tidk find`.")
+    }
 
     // calculate the number of chromosomes to plot with the length cutoff
     let chromosome_number = chromosome_number(&parsed_tsv, chromosome_cutoff);
@@ -30,7 +34,7 @@ pub fn plot(matches: &clap::ArgMatches) -> Result<()> {
     let height: i32 = height_subplot * chromosome_number as i32 + (2 * MARGIN);
 
     // generate the plot data (see struct PlotData)
-    let plot_data = generate_plot_data(parsed_tsv, height, *width, *height_subplot);
+    let plot_data = generate_plot_data(parsed_tsv, height, *width, *height_subplot, density);
 
     // filter the data based on the cutoff
     let plot_data_filtered: Vec<PlotData> = plot_data
@@ -74,6 +78,23 @@ pub struct TelomericRepeatRecord {
     pub forward_repeat_number: i32,
     pub reverse_repeat_number: i32,
     pub telomeric_repeat: String,
+    /// The proportion of the window covered by repeats, which
+    /// older TSVs don't have.
+    #[serde(default)]
+    pub repeat_fraction: Option<f64>,
+}
+
+impl TelomericRepeatRecord {
+    /// The value to plot for the window: either the number of
+    /// repeats on both strands, or the percentage of the window
+    /// covered by repeats.
+    fn value(&self, density: bool) -> f64 {
+        if density {
+            self.repeat_fraction.unwrap_or_default() * 100.0
+        } else {
+            (self.forward_repeat_number + self.reverse_repeat_number) as f64
+        }
+    }
 }
 
 /// This deserializes a TSV to a [`Vec<TelomericRepeatRecord>`].
@@ -132,9 +153,9 @@ fn scale_y(y: f64, a: f64, b: f64, min: f64, max: f64) -> f64 {
 /// This is also where the paths are scaled to
 /// the plot width and subplot height.
 fn make_path_element(
-    path_vec: Vec<(i32, i32)>,
+    path_vec: Vec<(i32, f64)>,
     x_max: usize,
-    y_max: usize,
+    y_max: f64,
     height: i32,
     width: i32,
     height_per_plot: i32,
@@ -161,11 +182,11 @@ fn make_path_element(
         height as f64
             - MARGIN as f64
             - scale_y(
-                path_vec[0].1 as f64,
+                path_vec[0].1,
                 0.0,
                 height_per_plot as f64,
                 0.0,
-                y_max as f64 + subplot_gap
+                y_max + subplot_gap
             )
     );
 
@@ -178,11 +199,11 @@ fn make_path_element(
             height as f64
                 - MARGIN as f64
                 - scale_y(
-                    element.1 as f64,
+                    element.1,
                     0.0,
                     height_per_plot as f64,
                     0.0,
-                    y_max as f64 + subplot_gap
+                    y_max + subplot_gap
                 )
         );
         bin += x_bin;
@@ -247,6 +268,7 @@ fn generate_plot_data(
     height: i32,
     width: i32,
     height_per_plot: i32,
+    density: bool,
 ) -> Vec<PlotData> {
     // so we can break the loop
     let file_length = parsed_tsv.len();
@@ -255,7 +277,7 @@ fn generate_plot_data(
     // a mutable vector to calculate svg path attribute
     let mut path_vec = Vec::new();
     let mut plot_data = Vec::new();
-    let mut y_max = 0;
+    let mut y_max = 0.0;
 
     loop {
         if it == file_length - 1 {
@@ -264,7 +286,7 @@ fn generate_plot_data(
             let path_element = match make_path_element(
                 path_vec.clone(),
                 path_vec.clone().len(),
-                y_max as usize,
+                y_max,
                 height,
                 width,
                 height_per_plot,
@@ -284,36 +306,28 @@ fn generate_plot_data(
 
         if parsed_tsv[it].id == parsed_tsv[it + 1].id {
             // calculate y max
-            if y_max <= parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number
-            {
-                y_max = parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number;
+            if y_max <= parsed_tsv[it].value(density) {
+                y_max = parsed_tsv[it].value(density);
             }
             // window (i.e x)
             // forward + reverse counts
-            path_vec.push((
-                parsed_tsv[it].window,
-                parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number,
-            ));
+            path_vec.push((parsed_tsv[it].window, parsed_tsv[it].value(density)));
             it += 1;
         } else {
             // want to calculate y_max and...
-            if y_max <= parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number
-            {
-                y_max = parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number;
+            if y_max <= parsed_tsv[it].value(density) {
+                y_max = parsed_tsv[it].value(density);
             }
             // the path vector for the last element (seems important for things which occur at the
             // ends of chromosomes right..? DOH)
-            path_vec.push((
-                parsed_tsv[it].window,
-                parsed_tsv[it].forward_repeat_number + parsed_tsv[it].reverse_repeat_number,
-            ));
+            path_vec.push((parsed_tsv[it].window, parsed_tsv[it].value(density)));
             // calculate the svg path element from path_vec here
             // there may not be a path element
             // so explicitly make a blank if there is not.
             let path_element = match make_path_element(
                 path_vec.clone(),
                 path_vec.clone().len(),
-                y_max as usize,
+                y_max,
                 height,
                 width,
                 height_per_plot,
@@ -330,7 +344,7 @@ fn generate_plot_data(
             });
             path_vec.clear();
             it += 1;
-            y_max = 0;
+            y_max = 0.0;
         }
     }
     plot_data
@@ -0,0 +1,1090 @@
+use anyhow::{bail, Context, Result};
+use bio::io::{fasta, fastq};
+use flate2::read::MultiGzDecoder;
+use rust_htslib::bam::{self, Read as BamRead};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::{read_to_string, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The magic number at the start of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// An iterator over the records of a sequence file. FASTQ records
+/// are converted to FASTA records, and their qualities dropped.
+pub type Records = Box<dyn Iterator<Item = Result<fasta::Record>> + Send>;
+
+/// An iterator over the records of a sequence file, each with the
+/// group it belongs to, if the records are grouped.
+pub type GroupedRecords = Box<dyn Iterator<Item = Result<(Option<String>, fasta::Record)>> + Send>;
+
+/// The sequence file formats which can be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Fasta,
+    Fastq,
+    /// SAM, BAM or CRAM, which are all read by htslib.
+    Alignment,
+}
+
+/// The magic number at the start of a gzip (or bgzip) file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The raw or decompressed input. It has to be [`Send`] for
+/// `par_bridge` in `tidk explore`.
+type Input = Box<dyn Read + Send>;
+
+/// Open a FASTA, FASTQ, SAM, BAM or CRAM file, and iterate over its
+/// records. FASTA and FASTQ may be uncompressed, gzip or bgzip
+/// compressed, and a path of `-` reads them from stdin. A CRAM is
+/// decoded with the `reference`, if given, or else the reference
+/// htslib finds from its header.
+pub fn records<P: AsRef<Path>>(path: P, reference: Option<&Path>) -> Result<Records> {
+    Ok(Box::new(
+        grouped_records(path, reference, false)?.map(|record| Ok(record?.1)),
+    ))
+}
+
+/// As [`records`], but optionally group the reads in an alignment file
+/// by the chromosome arm they are mapped to (see [`arm_group`]).
+pub fn grouped_records<P: AsRef<Path>>(
+    path: P,
+    reference: Option<&Path>,
+    group_by_arm: bool,
+) -> Result<GroupedRecords> {
+    let path = path.as_ref();
+    let (format, input) = open(path)?;
+    format_records(path, format, input, reference, group_by_arm)
+}
+
+/// Get the reference to decode a CRAM with from the command line
+/// options, if `--reference` was given.
+pub fn get_reference(matches: &clap::ArgMatches) -> Option<PathBuf> {
+    matches.get_one::<PathBuf>("reference").cloned()
+}
+
+/// The decompressed input, with the bytes read to detect its format
+/// put back in front.
+type Decompressed = BufReader<io::Chain<io::Cursor<Vec<u8>>, Input>>;
+
+/// Open a file (or stdin), decompressing it if need be, and detect
+/// its format. Gzip and bgzip are both decompressed as multi-member
+/// gzip.
+fn open(path: &Path) -> Result<(Format, Decompressed)> {
+    let mut input: Input = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).with_context(|| format!("Could not open {}", path.display()))?)
+    };
+
+    let magic = read_start(&mut input, GZIP_MAGIC.len())
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let is_gzip = magic == GZIP_MAGIC;
+    let input = io::Cursor::new(magic).chain(input);
+    let mut input: Input = if is_gzip {
+        Box::new(MultiGzDecoder::new(input))
+    } else {
+        Box::new(input)
+    };
+
+    let start =
+        read_start(&mut input, 64).with_context(|| format!("Could not read {}", path.display()))?;
+    let format =
+        detect_format(&start).with_context(|| format!("Could not read {}", path.display()))?;
+    Ok((format, BufReader::new(io::Cursor::new(start).chain(input))))
+}
+
+/// Read up to `length` bytes from the start of the input. Pipes can
+/// return less than was asked for, so keep reading until there's
+/// enough, or the input ends.
+fn read_start<R: Read>(input: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut start = Vec::with_capacity(length);
+    input.take(length as u64).read_to_end(&mut start)?;
+    Ok(start)
+}
+
+/// Iterate over the records of an opened file, in the given format.
+fn format_records(
+    path: &Path,
+    format: Format,
+    input: Decompressed,
+    reference: Option<&Path>,
+    group_by_arm: bool,
+) -> Result<GroupedRecords> {
+    if group_by_arm && format != Format::Alignment {
+        bail!("Grouping by chromosome arm needs mapped reads, in SAM, BAM or CRAM format.")
+    }
+
+    let records: GroupedRecords = match format {
+        Format::Fasta => Box::new(
+            fasta::Reader::from_bufread(input)
+                .records()
+                .map(|record| Ok((None, record?))),
+        ),
+        Format::Fastq => Box::new(fastq::Reader::from_bufread(input).records().map(|record| {
+            let record = record?;
+            Ok((
+                None,
+                fasta::Record::with_attrs(record.id(), record.desc(), record.seq()),
+            ))
+        })),
+        Format::Alignment => {
+            if path == Path::new("-") {
+                bail!("Reading SAM, BAM or CRAM from stdin is not supported.")
+            }
+            alignment_records(path, reference, group_by_arm)?
+        }
+    };
+
+    Ok(records)
+}
+
+/// Iterate over the reads in a SAM, BAM or CRAM file. The whole read
+/// sequence is used, including soft-clipped bases, as this is where
+/// telomeric sequence hanging off the end of a chromosome ends up.
+/// Secondary and supplementary alignments are skipped, so each read
+/// is only seen once, but unmapped reads are kept.
+fn alignment_records(
+    path: &Path,
+    reference: Option<&Path>,
+    group_by_arm: bool,
+) -> Result<GroupedRecords> {
+    let mut input = bam::Reader::from_path(path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+    if let Some(reference) = reference {
+        input.set_reference(reference).with_context(|| {
+            format!(
+                "Could not use {} as the reference of {}",
+                reference.display(),
+                path.display()
+            )
+        })?;
+    }
+    let path = path.to_path_buf();
+
+    // the names and lengths of the reference sequences
+    let header = input.header();
+    let targets: Vec<(String, u64)> = (0..header.target_count())
+        .map(|tid| {
+            (
+                String::from_utf8_lossy(header.tid2name(tid)).to_string(),
+                header.target_len(tid).unwrap_or(0),
+            )
+        })
+        .collect();
+
+    let mut record = bam::Record::new();
+    Ok(Box::new(std::iter::from_fn(move || loop {
+        match input.read(&mut record) {
+            None => return None,
+            Some(Err(e)) => {
+                // most likely a CRAM whose reference htslib couldn't find
+                return Some(Err(anyhow::Error::from(e).context(format!(
+                    "Could not read {}. A CRAM may need its reference, given with --reference",
+                    path.display()
+                ))));
+            }
+            Some(Ok(())) => {}
+        }
+        if record.is_secondary() || record.is_supplementary() {
+            continue;
+        }
+        let group = group_by_arm.then(|| arm_group(&record, &targets));
+        let id = String::from_utf8_lossy(record.qname()).to_string();
+        let sequence = record.seq().as_bytes();
+        return Some(Ok((group, fasta::Record::with_attrs(&id, None, &sequence))));
+    })))
+}
+
+/// The chromosome arm a read is mapped to, as `<chromosome>:left` or
+/// `<chromosome>:right`, depending on which half of the chromosome the
+/// middle of the alignment falls in. Unmapped reads are `unmapped`.
+fn arm_group(record: &bam::Record, targets: &[(String, u64)]) -> String {
+    if record.is_unmapped() || record.tid() < 0 {
+        return "unmapped".into();
+    }
+    let (name, length) = &targets[record.tid() as usize];
+    let middle = (record.pos() + record.cigar().end_pos()) as u64 / 2;
+    arm_name(name, middle, *length)
+}
+
+/// Name the arm of a chromosome which a position is on.
+fn arm_name(chromosome: &str, position: u64, length: u64) -> String {
+    if position < length / 2 {
+        format!("{}:left", chromosome)
+    } else {
+        format!("{}:right", chromosome)
+    }
+}
+
+/// A region of a sequence, in 0-based, half-open coordinates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// The sequence ID.
+    pub id: String,
+    /// Start of the region (0-based, inclusive).
+    pub start: usize,
+    /// End of the region (0-based, exclusive).
+    pub end: usize,
+    /// The length of the whole sequence.
+    pub length: usize,
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.id, self.start, self.end)
+    }
+}
+
+/// Which regions of an indexed FASTA to read, instead of
+/// the whole of every record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Regions {
+    /// This many bases at either end of each sequence.
+    Ends(usize),
+    /// The regions in a BED file.
+    Bed(PathBuf),
+}
+
+/// An iterator over the regions of a sequence file, each with
+/// its sequence as a FASTA record.
+pub type RegionRecords = Box<dyn Iterator<Item = Result<(Region, fasta::Record)>> + Send>;
+
+/// An indexed FASTA, which is read using the offsets in its faidx
+/// index, and if it's bgzip compressed, the offsets of the compressed
+/// blocks in its `.gzi` index.
+struct IndexedInput {
+    file: File,
+    /// The compressed and uncompressed offset of the start of each
+    /// bgzip block, if the FASTA is compressed.
+    blocks: Option<Vec<(u64, u64)>>,
+    /// Each sequence in the index, by ID.
+    index: HashMap<String, FaiEntry>,
+    /// The index file, for errors.
+    fai: PathBuf,
+}
+
+/// A sequence's line in a faidx index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FaiEntry {
+    /// The number of bases.
+    length: usize,
+    /// The (uncompressed) offset of the first base in the file.
+    offset: u64,
+    /// The number of bases on each line.
+    line_bases: usize,
+    /// The number of bytes on each line, including the line ending.
+    line_width: usize,
+}
+
+/// Get the regions to read from the command line options, if
+/// either `--ends` or `--regions` was given.
+pub fn get_regions(matches: &clap::ArgMatches) -> Option<Regions> {
+    if let Some(ends) = matches.get_one::<usize>("ends") {
+        Some(Regions::Ends(*ends))
+    } else {
+        matches
+            .get_one::<PathBuf>("regions")
+            .map(|bed| Regions::Bed(bed.clone()))
+    }
+}
+
+/// Iterate over the regions of a sequence file. Without any
+/// [`Regions`], every record is read in full, as in [`records`].
+/// Otherwise, the FASTA must be uncompressed or bgzip compressed,
+/// and only the regions are fetched, using the faidx index (which
+/// is built if it does not exist).
+pub fn region_records<P: AsRef<Path>>(
+    path: P,
+    reference: Option<&Path>,
+    regions: Option<&Regions>,
+) -> Result<RegionRecords> {
+    let path = path.as_ref();
+    let regions = match regions {
+        Some(r) => r,
+        None => {
+            return Ok(Box::new(records(path, reference)?.map(|record| {
+                let record = record?;
+                let region = Region {
+                    id: record.id().to_string(),
+                    start: 0,
+                    end: record.seq().len(),
+                    length: record.seq().len(),
+                };
+                Ok((region, record))
+            })))
+        }
+    };
+
+    let (mut input, regions) = open_indexed(path, regions)?;
+    Ok(Box::new(regions.into_iter().map(move |region| {
+        let record = input.fetch(&region)?;
+        Ok((region, record))
+    })))
+}
+
+/// Open an indexed FASTA, and find the regions to read from it. An
+/// uncompressed FASTA is indexed if the index is missing or out of
+/// date, but a bgzipped one needs to be indexed with `samtools faidx`.
+fn open_indexed(path: &Path, regions: &Regions) -> Result<(IndexedInput, Vec<Region>)> {
+    if path == Path::new("-") {
+        bail!("Reading regions needs an indexed FASTA file, not stdin.")
+    }
+    let mut file =
+        File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let is_gzip = read_start(&mut file, GZIP_MAGIC.len())? == GZIP_MAGIC;
+
+    let fai = index_path(path, "fai");
+    let indexed = is_indexed(path, &fai)?;
+    let blocks = if is_gzip {
+        let gzi = index_path(path, "gzi");
+        if !indexed || !is_indexed(path, &gzi)? {
+            bail!(
+                "{} is compressed, so it needs to be bgzip compressed and indexed with `samtools faidx {}`, which writes {} and {}.",
+                path.display(),
+                path.display(),
+                fai.display(),
+                gzi.display()
+            )
+        }
+        Some(
+            read_gzi(&gzi)
+                .with_context(|| format!("Could not read the index {}", gzi.display()))?,
+        )
+    } else {
+        if !indexed {
+            eprintln!("[+]\tIndexing {}", path.display());
+            file.rewind()?;
+            let index = build_fai(BufReader::new(&mut file))
+                .with_context(|| format!("Could not index {}", path.display()))?;
+            std::fs::write(&fai, index)
+                .with_context(|| format!("Could not write the index {}", fai.display()))?;
+        }
+        None
+    };
+
+    let sequences = read_fai(&fai).with_context(|| {
+        format!(
+            "Could not read the index {}, try `samtools faidx {}`",
+            fai.display(),
+            path.display()
+        )
+    })?;
+    let lengths: Vec<(String, usize)> = sequences
+        .iter()
+        .map(|(id, entry)| (id.clone(), entry.length))
+        .collect();
+    let regions = match regions {
+        Regions::Ends(ends) => terminal_regions(&lengths, *ends),
+        Regions::Bed(bed) => read_bed(bed, &lengths)?,
+    };
+
+    let input = IndexedInput {
+        file,
+        blocks,
+        index: sequences.into_iter().collect(),
+        fai,
+    };
+    Ok((input, regions))
+}
+
+/// The path of an index of a file, e.g. `genome.fa.fai`.
+fn index_path(path: &Path, extension: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), extension))
+}
+
+/// Whether an index exists, and was written after the file it indexes.
+fn is_indexed(path: &Path, index: &Path) -> Result<bool> {
+    if !index.exists() {
+        return Ok(false);
+    }
+    Ok(index.metadata()?.modified()? >= path.metadata()?.modified()?)
+}
+
+impl IndexedInput {
+    /// Fetch the sequence of a region as a FASTA record.
+    fn fetch(&mut self, region: &Region) -> Result<fasta::Record> {
+        let seq = self.fetch_range(&region.id, region.start, region.end)?;
+        Ok(fasta::Record::with_attrs(&region.id, None, &seq))
+    }
+
+    /// Fetch the sequence from `start` to `end` (0-based, half-open).
+    fn fetch_range(&mut self, id: &str, start: usize, end: usize) -> Result<Vec<u8>> {
+        let entry = self
+            .index
+            .get(id)
+            .with_context(|| format!("Sequence {} is not in {}", id, self.fai.display()))?;
+        if start >= end || end > entry.length || entry.line_bases == 0 {
+            bail!(
+                "{}:{}-{} is not a region in {}",
+                id,
+                start,
+                end,
+                self.fai.display()
+            )
+        }
+
+        // the offset in the file of a base
+        let offset = |position: usize| {
+            entry.offset
+                + (position / entry.line_bases * entry.line_width + position % entry.line_bases)
+                    as u64
+        };
+        let (from, to) = (offset(start), offset(end - 1) + 1);
+        let stale = || {
+            format!(
+                "The index {} doesn't match the FASTA, so may be out of date. Try deleting it, or `samtools faidx`.",
+                self.fai.display()
+            )
+        };
+
+        let mut bytes = Vec::with_capacity((to - from) as usize);
+        let input: Box<dyn Read + '_> = match &self.blocks {
+            None => {
+                self.file.seek(SeekFrom::Start(from))?;
+                Box::new(&mut self.file)
+            }
+            Some(blocks) => {
+                // start decompressing at the block the sequence starts in
+                let block = blocks.partition_point(|(_, uncompressed)| *uncompressed <= from) - 1;
+                let (compressed, uncompressed) = blocks[block];
+                self.file.seek(SeekFrom::Start(compressed))?;
+                let mut decoder = MultiGzDecoder::new(BufReader::new(&mut self.file));
+                io::copy(
+                    &mut (&mut decoder).take(from - uncompressed),
+                    &mut io::sink(),
+                )?;
+                Box::new(decoder)
+            }
+        };
+        input.take(to - from).read_to_end(&mut bytes)?;
+
+        // check the line endings are where the index says they are
+        let mut sequence = Vec::with_capacity(end - start);
+        for (i, byte) in bytes.iter().enumerate() {
+            let column = (from - entry.offset) as usize % entry.line_width + i;
+            let is_line_end = matches!(byte, b'\n' | b'\r');
+            if (column % entry.line_width < entry.line_bases) == is_line_end || *byte == b'>' {
+                bail!(stale())
+            }
+            if !is_line_end {
+                sequence.push(*byte);
+            }
+        }
+        if sequence.len() != end - start {
+            bail!(stale())
+        }
+        Ok(sequence)
+    }
+}
+
+/// Reads the sequences of a file, or the regions of an indexed FASTA,
+/// a chunk at a time, so a whole chromosome never needs to be held in
+/// memory. Move on to each sequence with [`SequenceReader::next_sequence`],
+/// then [`SequenceReader::read`] it until it's finished.
+pub struct SequenceReader(Source);
+
+/// Where a [`SequenceReader`] reads the sequences from.
+enum Source {
+    /// FASTA, which is parsed as it's read.
+    Fasta(FastaReader<Decompressed>),
+    /// FASTQ or alignments, whose reads are short enough to read whole,
+    /// with the current read and how much of it has been read.
+    Records {
+        records: Records,
+        current: Option<(fasta::Record, usize)>,
+    },
+    /// The regions of an indexed FASTA, with the current region and
+    /// how far through it has been read.
+    Indexed {
+        input: IndexedInput,
+        regions: std::vec::IntoIter<Region>,
+        current: Option<(Region, usize)>,
+    },
+}
+
+/// The sequence a [`SequenceReader`] has moved on to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceHeader {
+    /// The sequence ID.
+    pub id: String,
+    /// The region of the sequence, if only a region is read.
+    pub region: Option<Region>,
+}
+
+/// Open a file to read its sequences a chunk at a time. As in
+/// [`region_records`], if there are [`Regions`], only they are read
+/// from an indexed FASTA.
+pub fn sequence_reader<P: AsRef<Path>>(
+    path: P,
+    reference: Option<&Path>,
+    regions: Option<&Regions>,
+) -> Result<SequenceReader> {
+    let path = path.as_ref();
+    let source = match regions {
+        Some(regions) => {
+            let (input, regions) = open_indexed(path, regions)?;
+            Source::Indexed {
+                input,
+                regions: regions.into_iter(),
+                current: None,
+            }
+        }
+        None => match open(path)? {
+            (Format::Fasta, input) => Source::Fasta(FastaReader::new(input)),
+            (format, input) => Source::Records {
+                records: Box::new(
+                    format_records(path, format, input, reference, false)?
+                        .map(|record| Ok(record?.1)),
+                ),
+                current: None,
+            },
+        },
+    };
+    Ok(SequenceReader(source))
+}
+
+impl SequenceReader {
+    /// Move on to the next sequence, skipping whatever is left of the
+    /// current one. Returns `None` once every sequence has been read.
+    pub fn next_sequence(&mut self) -> Result<Option<SequenceHeader>> {
+        match &mut self.0 {
+            Source::Fasta(fasta) => Ok(fasta
+                .next_header()?
+                .map(|id| SequenceHeader { id, region: None })),
+            Source::Records { records, current } => {
+                *current = records.next().transpose()?.map(|record| (record, 0));
+                Ok(current.as_ref().map(|(record, _)| SequenceHeader {
+                    id: record.id().to_string(),
+                    region: None,
+                }))
+            }
+            Source::Indexed {
+                regions, current, ..
+            } => {
+                *current = regions.next().map(|region| {
+                    let start = region.start;
+                    (region, start)
+                });
+                Ok(current.as_ref().map(|(region, _)| SequenceHeader {
+                    id: region.id.clone(),
+                    region: Some(region.clone()),
+                }))
+            }
+        }
+    }
+
+    /// Append up to `bases` more bases of the current sequence to the
+    /// `buffer`, and return how many were read. Fewer are only read once
+    /// the sequence is finished.
+    pub fn read(&mut self, buffer: &mut Vec<u8>, bases: usize) -> Result<usize> {
+        match &mut self.0 {
+            Source::Fasta(fasta) => fasta.read(buffer, bases),
+            Source::Records { current, .. } => {
+                let Some((record, position)) = current else {
+                    return Ok(0);
+                };
+                let sequence = &record.seq()[*position..];
+                let read = sequence.len().min(bases);
+                buffer.extend_from_slice(&sequence[..read]);
+                *position += read;
+                Ok(read)
+            }
+            Source::Indexed { input, current, .. } => {
+                let Some((region, position)) = current else {
+                    return Ok(0);
+                };
+                let end = region.end.min(*position + bases);
+                if end == *position {
+                    return Ok(0);
+                }
+                buffer.extend(input.fetch_range(&region.id, *position, end)?);
+                let read = end - *position;
+                *position = end;
+                Ok(read)
+            }
+        }
+    }
+}
+
+/// Parses FASTA as it's read, so the sequence of each record can be
+/// read a chunk at a time, rather than the whole record at once.
+struct FastaReader<R> {
+    reader: R,
+    /// Whether the reader is at the start of a line.
+    line_start: bool,
+}
+
+impl<R: BufRead> FastaReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line_start: true,
+        }
+    }
+
+    /// Skip whatever is left of the current record, and return the ID
+    /// of the next record from its header.
+    fn next_header(&mut self) -> Result<Option<String>> {
+        loop {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                return Ok(None);
+            }
+            if self.line_start && available[0] == b'>' {
+                break;
+            }
+            // skip to the end of the line
+            let (consumed, line_start) = match available.iter().position(|b| *b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (available.len(), false),
+            };
+            self.reader.consume(consumed);
+            self.line_start = line_start;
+        }
+
+        let mut header = Vec::new();
+        self.reader.read_until(b'\n', &mut header)?;
+        self.line_start = true;
+        // the ID is everything up to the first whitespace, as in bio
+        let id = String::from_utf8_lossy(&header[1..])
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Ok(Some(id))
+    }
+
+    /// Append up to `bases` more bases of the current record to the
+    /// `buffer`, skipping line endings and stopping at the next header.
+    /// Returns how many were read.
+    fn read(&mut self, buffer: &mut Vec<u8>, bases: usize) -> Result<usize> {
+        let mut read = 0;
+        while read < bases {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() || (self.line_start && available[0] == b'>') {
+                break;
+            }
+            let line_end = available.iter().position(|b| *b == b'\n');
+            let line = &available[..line_end.unwrap_or(available.len())];
+            let take = line.len().min(bases - read);
+
+            let before = buffer.len();
+            buffer.extend(line[..take].iter().filter(|b| !b.is_ascii_whitespace()));
+            read += buffer.len() - before;
+
+            let mut consumed = take;
+            if take == line.len() && line_end.is_some() {
+                consumed += 1;
+                self.line_start = true;
+            } else if take > 0 {
+                self.line_start = false;
+            }
+            self.reader.consume(consumed);
+        }
+        Ok(read)
+    }
+}
+
+/// Read the sequences, in order, from a faidx index.
+fn read_fai(path: &Path) -> Result<Vec<(String, FaiEntry)>> {
+    let mut sequences = Vec::new();
+    for line in read_to_string(path)?.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            bail!("Malformed line in {}: {}", path.display(), line)
+        }
+        let entry = FaiEntry {
+            length: fields[1].parse()?,
+            offset: fields[2].parse()?,
+            line_bases: fields[3].parse()?,
+            line_width: fields[4].parse()?,
+        };
+        sequences.push((fields[0].to_string(), entry));
+    }
+    Ok(sequences)
+}
+
+/// Index an uncompressed FASTA, returning the faidx index. As for
+/// `samtools faidx`, every line of a sequence but the last must be
+/// the same length.
+fn build_fai<R: BufRead>(mut reader: R) -> Result<String> {
+    let mut index = String::new();
+    let mut current: Option<(String, FaiEntry)> = None;
+    // whether the current sequence has had a short (so last) line
+    let mut last_line = false;
+    let mut offset = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let width = reader.read_until(b'\n', &mut line)?;
+        if width == 0 {
+            break;
+        }
+        offset += width as u64;
+
+        if line[0] == b'>' {
+            if let Some((id, entry)) = current.take() {
+                index += &fai_line(&id, &entry);
+            }
+            let id = String::from_utf8_lossy(&line[1..])
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            let entry = FaiEntry {
+                length: 0,
+                offset,
+                line_bases: 0,
+                line_width: 0,
+            };
+            current = Some((id, entry));
+            last_line = false;
+            continue;
+        }
+
+        let Some((id, entry)) = current.as_mut() else {
+            bail!("The FASTA doesn't start with a header.")
+        };
+        let bases = line
+            .iter()
+            .rposition(|b| *b != b'\n' && *b != b'\r')
+            .map_or(0, |i| i + 1);
+        if bases == 0 {
+            last_line = true;
+            continue;
+        }
+        if last_line {
+            bail!(
+                "Sequence {} has lines of different lengths, so can't be indexed.",
+                id
+            )
+        }
+        if entry.line_bases == 0 {
+            (entry.line_bases, entry.line_width) = (bases, width);
+        } else if bases > entry.line_bases {
+            bail!(
+                "Sequence {} has lines of different lengths, so can't be indexed.",
+                id
+            )
+        } else if bases < entry.line_bases || width != entry.line_width {
+            last_line = true;
+        }
+        entry.length += bases;
+    }
+    if let Some((id, entry)) = current {
+        index += &fai_line(&id, &entry);
+    }
+    Ok(index)
+}
+
+/// A line of a faidx index.
+fn fai_line(id: &str, entry: &FaiEntry) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        id, entry.length, entry.offset, entry.line_bases, entry.line_width
+    )
+}
+
+/// Read the compressed and uncompressed offsets of each block from a
+/// bgzip `.gzi` index. This is the number of blocks, then the offsets
+/// of each block after the first, all as little-endian `u64`s.
+fn read_gzi(path: &Path) -> Result<Vec<(u64, u64)>> {
+    let bytes = std::fs::read(path)?;
+    let numbers: Vec<u64> = bytes
+        .chunks_exact(8)
+        .map(|n| u64::from_le_bytes(n.try_into().expect("chunks of 8")))
+        .collect();
+    match numbers.split_first() {
+        Some((count, offsets)) if bytes.len() % 8 == 0 && offsets.len() as u64 == count * 2 => {
+            let mut blocks = vec![(0, 0)];
+            blocks.extend(offsets.chunks_exact(2).map(|pair| (pair[0], pair[1])));
+            Ok(blocks)
+        }
+        _ => bail!("Malformed index {}", path.display()),
+    }
+}
+
+/// The regions `ends` bases from either end of each sequence. Sequences
+/// shorter than twice this are read whole.
+fn terminal_regions(sequences: &[(String, usize)], ends: usize) -> Vec<Region> {
+    let mut regions = Vec::new();
+    for (id, length) in sequences {
+        if *length <= ends * 2 {
+            regions.push(Region {
+                id: id.clone(),
+                start: 0,
+                end: *length,
+                length: *length,
+            });
+        } else {
+            regions.push(Region {
+                id: id.clone(),
+                start: 0,
+                end: ends,
+                length: *length,
+            });
+            regions.push(Region {
+                id: id.clone(),
+                start: length - ends,
+                end: *length,
+                length: *length,
+            });
+        }
+    }
+    regions
+}
+
+/// Read the regions from a BED file, checking them against the
+/// sequences in the index. Ends past the end of a sequence are
+/// clipped.
+fn read_bed(path: &Path, sequences: &[(String, usize)]) -> Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for line in read_to_string(path)?.lines() {
+        // skip headers and comments
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            bail!("Malformed line in {}: {}", path.display(), line)
+        }
+        let id = fields[0];
+        let start: usize = fields[1].parse()?;
+        let end: usize = fields[2].parse()?;
+
+        let length = match sequences.iter().find(|(seq_id, _)| seq_id == id) {
+            Some((_, length)) => *length,
+            None => bail!("Sequence {} in {} is not in the FASTA.", id, path.display()),
+        };
+        let end = end.min(length);
+        if start >= end {
+            bail!("Empty region in {}: {}", path.display(), line)
+        }
+        regions.push(Region {
+            id: id.to_string(),
+            start,
+            end,
+            length,
+        });
+    }
+    Ok(regions)
+}
+
+/// Detect the format of the (decompressed) input from its first
+/// few bytes. Empty input is treated as an empty FASTA.
+fn detect_format(start: &[u8]) -> Result<Format> {
+    if is_alignment(start) {
+        return Ok(Format::Alignment);
+    }
+    if start.starts_with(&ZSTD_MAGIC) {
+        bail!("zstd compressed input is not supported, please decompress it first (e.g. `zstd -dc <FILE> | tidk <SUBCOMMAND> -`).")
+    }
+    match start.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'>') | None => Ok(Format::Fasta),
+        Some(b'@') => Ok(Format::Fastq),
+        Some(_) => {
+            bail!("The input is not FASTA or FASTQ, or is compressed with an unsupported format.")
+        }
+    }
+}
+
+/// Whether the (decompressed) input is BAM, CRAM or SAM. SAM is
+/// recognised by its header, as otherwise it looks like FASTQ.
+fn is_alignment(start: &[u8]) -> bool {
+    start.starts_with(b"BAM\x01")
+        || start.starts_with(b"CRAM")
+        || (start.len() >= 4
+            && start[0] == b'@'
+            && start[1..3].iter().all(|b| b.is_ascii_uppercase())
+            && start[3] == b'\t')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(b">chr1\nACGT").unwrap(), Format::Fasta);
+        assert_eq!(detect_format(b"\n@read1\nACGT").unwrap(), Format::Fastq);
+        assert_eq!(detect_format(b"").unwrap(), Format::Fasta);
+        assert!(detect_format(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]).is_err());
+        assert!(detect_format(b"ACGT").is_err());
+        assert_eq!(detect_format(b"BAM\x01...").unwrap(), Format::Alignment);
+        assert_eq!(
+            detect_format(b"@HD\tVN:1.6\n@SQ\tSN:chr1").unwrap(),
+            Format::Alignment
+        );
+        assert_eq!(detect_format(b"@HDR01\nACGT").unwrap(), Format::Fastq);
+    }
+
+    #[test]
+    fn test_fasta_reader() {
+        let fasta = b">chr1 a description\nACGT\nacg\r\nTA\n\n>chr2\nNNNN\n>chr3\n";
+        // however much is read at a time
+        for bases in [1, 3, 100] {
+            let mut reader = FastaReader::new(io::Cursor::new(fasta));
+            let mut sequences = Vec::new();
+            while let Some(id) = reader.next_header().unwrap() {
+                let mut sequence = Vec::new();
+                while reader.read(&mut sequence, bases).unwrap() == bases {}
+                sequences.push((id, String::from_utf8(sequence).unwrap()));
+            }
+            assert_eq!(
+                sequences,
+                vec![
+                    ("chr1".to_string(), "ACGTacgTA".to_string()),
+                    ("chr2".to_string(), "NNNN".to_string()),
+                    ("chr3".to_string(), "".to_string())
+                ]
+            );
+        }
+
+        // skipping the rest of a record
+        let mut reader = FastaReader::new(io::Cursor::new(fasta));
+        reader.next_header().unwrap();
+        let mut sequence = Vec::new();
+        assert_eq!(reader.read(&mut sequence, 2).unwrap(), 2);
+        assert_eq!(reader.next_header().unwrap(), Some("chr2".to_string()));
+    }
+
+    #[test]
+    fn test_open() {
+        let dir = std::env::temp_dir();
+        assert!(open(&dir.join("tidk_test_does_not_exist.fa")).is_err());
+        assert!(records(dir.join("tidk_test_does_not_exist.fa"), None).is_err());
+
+        // multi-member gzip, as bgzip writes
+        let mut compressed = Vec::new();
+        for member in [&b">chr1\nACGT"[..], b"ACGT\n>chr2\nTTAGGG\n"] {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            std::io::Write::write_all(&mut encoder, member).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+        let path = dir.join(format!("tidk_test_open_{}.fa.gz", std::process::id()));
+        std::fs::write(&path, compressed).unwrap();
+        let sequences: Vec<Vec<u8>> = records(&path, None)
+            .unwrap()
+            .map(|record| record.unwrap().seq().to_vec())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sequences, [b"ACGTACGT".to_vec(), b"TTAGGG".to_vec()]);
+    }
+
+    // a directory for a test's files, which is removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(test: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("tidk_test_{}_{}", test, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const INDEXED_FASTA: &[u8] =
+        b">chr1 description\nACGTA\nCCGGT\nTT\n>chr2\nAAAAA\nC\n>chr3\r\nGGG\r\n";
+
+    #[test]
+    fn test_build_fai() {
+        assert_eq!(
+            build_fai(INDEXED_FASTA).unwrap(),
+            "chr1\t12\t18\t5\t6\nchr2\t6\t39\t5\t6\nchr3\t3\t54\t3\t5\n"
+        );
+        assert!(build_fai(&b">chr1\nACG\nACGT\n"[..]).is_err());
+        assert!(build_fai(&b">chr1\nACGT\nAC\nACGT\n"[..]).is_err());
+        assert!(build_fai(&b"ACGT\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_indexed_input() {
+        let dir = TestDir::new("indexed_input");
+        let sequences = [
+            ("chr1", "ACGTACCGGTTT"),
+            ("chr2", "AAAAAC"),
+            ("chr3", "GGG"),
+        ];
+        let path = dir.0.join("genome.fa");
+        std::fs::write(&path, INDEXED_FASTA).unwrap();
+        assert!(open_indexed(&dir.0.join("missing.fa"), &Regions::Ends(2)).is_err());
+
+        // the index is built, then the same regions are read from bgzip
+        let (mut input, regions) = open_indexed(&path, &Regions::Ends(2)).unwrap();
+        assert!(path.with_extension("fa.fai").exists());
+        assert_eq!(regions.len(), 5);
+
+        let gz_path = dir.0.join("genome.fa.gz");
+        let (mut compressed, mut gzi) = (Vec::new(), Vec::new());
+        // blocks of 7 bytes, so bases are split across them
+        for (i, block) in INDEXED_FASTA.chunks(7).enumerate() {
+            if i > 0 {
+                gzi.extend((compressed.len() as u64).to_le_bytes());
+                gzi.extend((i as u64 * 7).to_le_bytes());
+            }
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            std::io::Write::write_all(&mut encoder, block).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+        let blocks = (INDEXED_FASTA.len() as u64).div_ceil(7) - 1;
+        gzi.splice(0..0, blocks.to_le_bytes());
+        std::fs::write(&gz_path, compressed).unwrap();
+        // without the index
+        assert!(open_indexed(&gz_path, &Regions::Ends(2)).is_err());
+        std::fs::copy(
+            path.with_extension("fa.fai"),
+            gz_path.with_extension("gz.fai"),
+        )
+        .unwrap();
+        std::fs::write(gz_path.with_extension("gz.gzi"), gzi).unwrap();
+        let (mut gz_input, _) = open_indexed(&gz_path, &Regions::Ends(2)).unwrap();
+
+        for (id, sequence) in sequences {
+            for start in 0..sequence.len() {
+                for end in start + 1..=sequence.len() {
+                    let expected = &sequence.as_bytes()[start..end];
+                    assert_eq!(input.fetch_range(id, start, end).unwrap(), expected);
+                    assert_eq!(gz_input.fetch_range(id, start, end).unwrap(), expected);
+                }
+            }
+        }
+        assert!(input.fetch_range("chr1", 0, 13).is_err());
+        assert!(input.fetch_range("chr1", 5, 5).is_err());
+        assert!(input.fetch_range("chr4", 0, 1).is_err());
+
+        // an index which doesn't match the FASTA
+        std::fs::write(path.with_extension("fa.fai"), "chr1\t12\t14\t5\t6\n").unwrap();
+        let (mut input, _) = open_indexed(&path, &Regions::Ends(2)).unwrap();
+        assert!(input.fetch_range("chr1", 0, 12).is_err());
+    }
+
+    #[test]
+    fn test_arm_name() {
+        assert_eq!(arm_name("chr1", 10, 1000), "chr1:left");
+        assert_eq!(arm_name("chr1", 990, 1000), "chr1:right");
+    }
+
+    #[test]
+    fn test_terminal_regions() {
+        let sequences = vec![("chr1".to_string(), 1000), ("chr2".to_string(), 150)];
+        let regions = terminal_regions(&sequences, 100);
+
+        assert_eq!(regions.len(), 3);
+        assert_eq!((regions[0].start, regions[0].end), (0, 100));
+        assert_eq!((regions[1].start, regions[1].end), (900, 1000));
+        assert_eq!(regions[2].to_string(), "chr2:0-150");
+        assert_eq!(regions[1].length, 1000);
+    }
+}
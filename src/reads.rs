@@ -0,0 +1,313 @@
+use crate::call::{self, TelomericArray};
+use crate::{reader, summary, SubCommand};
+use anyhow::{bail, Result};
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, File};
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str;
+
+/// The entry point for `tidk reads`.
+///
+/// Stream through (long) reads, calling telomeric arrays in each
+/// read, and keep those at either end of the read. These are written
+/// out per read, along with a histogram of their lengths.
+pub fn reads(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
+    let input_reads = matches
+        .get_one::<PathBuf>("fasta")
+        .expect("errored by clap");
+    // reads mapped to a reference can be grouped by chromosome arm
+    let group_by_arm = matches.get_flag("group-by-arm");
+    let reference = reader::get_reference(matches);
+    let records = reader::grouped_records(input_reads, reference.as_deref(), group_by_arm)?;
+
+    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+    eprintln!(
+        "[+]\tFinding telomeric reads for repeat(s): {}",
+        telomeric_repeats.join(", ")
+    );
+
+    let max_gap = *matches
+        .get_one::<usize>("max-gap")
+        .expect("defaulted by clap");
+    let min_copies = *matches
+        .get_one::<usize>("min-copies")
+        .expect("defaulted by clap");
+    let max_distance = *matches
+        .get_one::<usize>("max-distance")
+        .expect("defaulted by clap");
+    let bin_size = *matches
+        .get_one::<usize>("bin-size")
+        .expect("defaulted by clap");
+    if bin_size == 0 {
+        bail!("The histogram bin size must be greater than zero.")
+    }
+    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("errored by clap");
+
+    // create directory for output
+    create_dir_all(outdir)?;
+
+    // create files
+    let reads_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomeric_reads.tsv"
+    );
+    let reads_file = File::create(&reads_name)?;
+    let mut reads_file = LineWriter::new(reads_file);
+    write!(
+        reads_file,
+        "read_id\tread_length\tread_end\ttelomeric_repeat\tarray_start\tarray_end\tarray_length\tstrand\tcopies\tpurity\tdistance_to_end\texpected_orientation"
+    )?;
+    if group_by_arm {
+        write!(reads_file, "\tgroup")?;
+    }
+    writeln!(reads_file)?;
+
+    let mut lengths = Vec::new();
+    let (mut read_number, mut telomeric_read_number) = (0, 0);
+    // stream over the reads
+    for result in records {
+        let (group, record) = result?;
+        let sequence = str::from_utf8(record.seq())?.to_uppercase();
+
+        let telomeres = read_telomeres(
+            &sequence,
+            &telomeric_repeats,
+            max_gap,
+            min_copies,
+            max_distance,
+        );
+        for telomere in &telomeres {
+            write_telomere(
+                &mut reads_file,
+                record.id(),
+                sequence.len(),
+                telomere,
+                group.as_deref(),
+            )?;
+            lengths.push(telomere.array.len());
+        }
+
+        read_number += 1;
+        if !telomeres.is_empty() {
+            telomeric_read_number += 1;
+        }
+    }
+    eprintln!(
+        "[+]\tFinished reading: {} of {} reads have terminal telomeric arrays.",
+        telomeric_read_number, read_number
+    );
+
+    // and the histogram of telomere lengths
+    let histogram_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomere_length_histogram.tsv"
+    );
+    let histogram_file = File::create(&histogram_name)?;
+    let mut histogram_file = LineWriter::new(histogram_file);
+    writeln!(histogram_file, "bin_start\tbin_end\tcount")?;
+    for (i, count) in histogram(&lengths, bin_size).iter().enumerate() {
+        writeln!(
+            histogram_file,
+            "{}\t{}\t{}",
+            i * bin_size,
+            (i + 1) * bin_size,
+            count
+        )?;
+    }
+    eprintln!("[+]\tWritten to: {} and {}", reads_name, histogram_name);
+
+    // optional log file
+    sc.log(matches)?;
+
+    Ok(())
+}
+
+/// Which end of a read a telomeric array is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadEnd {
+    /// The start of the read.
+    Start,
+    /// The end of the read.
+    End,
+    /// The array covers the whole read, so we can't tell.
+    Both,
+}
+
+impl Display for ReadEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadEnd::Start => write!(f, "start"),
+            ReadEnd::End => write!(f, "end"),
+            ReadEnd::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// A telomeric array at one end of a read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadTelomere {
+    /// Which end of the read the array is at.
+    pub read_end: ReadEnd,
+    /// The telomeric repeat making up the array.
+    pub telomeric_repeat: String,
+    /// The array itself, in read coordinates.
+    pub array: TelomericArray,
+    /// The distance of the array from the end of the read.
+    pub distance: usize,
+    /// Whether the array is on the strand we expect at this end
+    /// of the read, as in `tidk summary`.
+    pub expected_orientation: bool,
+}
+
+/// Call the telomeric arrays in an (uppercase) read for each telomeric
+/// repeat, and keep the closest array to each end of the read, if it
+/// is within `max_distance` of the end.
+pub fn read_telomeres(
+    sequence: &str,
+    telomeric_repeats: &[String],
+    max_gap: usize,
+    min_copies: usize,
+    max_distance: usize,
+) -> Vec<ReadTelomere> {
+    let mut arrays = Vec::new();
+    for telomeric_repeat in telomeric_repeats {
+        arrays.extend(
+            call::call_arrays(sequence, telomeric_repeat, max_gap, min_copies)
+                .into_iter()
+                .map(|a| (telomeric_repeat.as_str(), a)),
+        );
+    }
+
+    terminal_telomeres(sequence.len(), &arrays, max_distance)
+}
+
+/// Find the arrays closest to each end of a read, within `max_distance`
+/// of the end. An array within reach of both ends is only reported once.
+fn terminal_telomeres(
+    length: usize,
+    arrays: &[(&str, TelomericArray)],
+    max_distance: usize,
+) -> Vec<ReadTelomere> {
+    let start = arrays
+        .iter()
+        .filter(|(_, a)| a.start <= max_distance)
+        .min_by_key(|(_, a)| a.start);
+    let end = arrays
+        .iter()
+        .filter(|(_, a)| length - a.end <= max_distance)
+        .max_by_key(|(_, a)| a.end);
+
+    let telomere = |(telomeric_repeat, array): &(&str, TelomericArray), read_end| {
+        let (distance, is_left) = match read_end {
+            ReadEnd::Start => (array.start, true),
+            ReadEnd::End => (length - array.end, false),
+            ReadEnd::Both => (array.start.min(length - array.end), true),
+        };
+        ReadTelomere {
+            read_end,
+            telomeric_repeat: telomeric_repeat.to_string(),
+            array: array.clone(),
+            distance,
+            expected_orientation: array.strand
+                == summary::expected_strand(telomeric_repeat, is_left),
+        }
+    };
+
+    match (start, end) {
+        (Some(s), Some(e)) if s == e => vec![telomere(s, ReadEnd::Both)],
+        (s, e) => s
+            .map(|s| telomere(s, ReadEnd::Start))
+            .into_iter()
+            .chain(e.map(|e| telomere(e, ReadEnd::End)))
+            .collect(),
+    }
+}
+
+/// Bin the telomere lengths into a histogram, from zero up to the bin
+/// containing the longest telomere.
+fn histogram(lengths: &[usize], bin_size: usize) -> Vec<usize> {
+    let bins = match lengths.iter().max() {
+        Some(max) => max / bin_size + 1,
+        None => 0,
+    };
+    let mut counts = vec![0; bins];
+    for length in lengths {
+        counts[length / bin_size] += 1;
+    }
+    counts
+}
+
+/// Write a row of the per-read TSV, with the read's group if
+/// the reads are grouped.
+fn write_telomere<T: std::io::Write>(
+    file: &mut LineWriter<T>,
+    id: &str,
+    read_length: usize,
+    telomere: &ReadTelomere,
+    group: Option<&str>,
+) -> Result<()> {
+    write!(
+        file,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}\t{}",
+        id,
+        read_length,
+        telomere.read_end,
+        telomere.telomeric_repeat,
+        telomere.array.start,
+        telomere.array.end,
+        telomere.array.len(),
+        telomere.array.strand,
+        telomere.array.copies,
+        telomere.array.purity,
+        telomere.distance,
+        telomere.expected_orientation
+    )?;
+    if let Some(group) = group {
+        write!(file, "\t{}", group)?;
+    }
+    writeln!(file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::Strand;
+
+    #[test]
+    fn test_read_telomeres() {
+        // C-rich telomere at the start of a read, nothing at the end
+        let read = "CCTAACCTAACCTAACCTAAGCATGCATGCATGCATGCATGCAT";
+        let telomeres = read_telomeres(read, &["TTAGG".into()], 0, 3, 5);
+
+        assert_eq!(telomeres.len(), 1);
+        assert_eq!(telomeres[0].read_end, ReadEnd::Start);
+        assert_eq!(telomeres[0].array.strand, Strand::Reverse);
+        assert_eq!(telomeres[0].array.len(), 20);
+        assert!(telomeres[0].expected_orientation);
+    }
+
+    #[test]
+    fn test_read_telomeres_whole_read() {
+        let read = "TTAGGTTAGGTTAGGTTAGG";
+        let telomeres = read_telomeres(read, &["TTAGG".into()], 0, 3, 5);
+
+        assert_eq!(telomeres.len(), 1);
+        assert_eq!(telomeres[0].read_end, ReadEnd::Both);
+    }
+
+    #[test]
+    fn test_histogram() {
+        assert_eq!(histogram(&[10, 150, 199, 450], 100), vec![1, 2, 0, 0, 1]);
+        assert!(histogram(&[], 100).is_empty());
+    }
+}
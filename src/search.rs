@@ -1,79 +1,69 @@
-use crate::{utils, SubCommand};
-use anyhow::Result;
-use bio::io::fasta;
-use std::fs::{create_dir_all, File};
+use crate::{bigwig::BigWigWriter, call, gff::Gff3Writer, reader, utils, SubCommand};
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use std::fs::{create_dir_all, read_to_string, File};
 use std::io::LineWriter;
 use std::io::Write;
-use std::path::PathBuf;
-use std::str;
+use std::path::{Path, PathBuf};
 
 /// The entry point for `tidk search`.
 pub fn search(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     let input_fasta = matches
         .get_one::<PathBuf>("fasta")
         .expect("errored by clap");
-    let reader = fasta::Reader::from_file(input_fasta)?;
+    // optionally only read some regions of an indexed fasta
+    let regions = reader::get_regions(matches);
+    let reference = reader::get_reference(matches);
+    let records = reader::sequence_reader(input_fasta, reference.as_deref(), regions.as_ref())?;
 
-    let telomeric_repeat = matches
-        .get_one::<String>("string")
-        .expect("errored by clap");
+    let telomeric_repeats = get_search_strings(matches)?;
     let extension = matches
         .get_one::<String>("extension")
         .expect("defaulted by clap");
     eprintln!(
-        "[+]\tSearching genome for telomeric repeat: {}",
-        telomeric_repeat
+        "[+]\tSearching genome for telomeric repeat(s): {}",
+        telomeric_repeats.join(", ")
     );
 
     let window_size = *matches
         .get_one::<usize>("window")
         .expect("defaulted by clap");
+    let step = get_step(matches, window_size)?;
     let outdir = matches
         .get_one::<PathBuf>("dir")
         .expect("defaulted by clap");
     let output = matches
-        .get_one::<String>("output")
+        .get_one::<PathBuf>("output")
         .expect("errored by clap");
 
     // create directory for output
     create_dir_all(outdir)?;
 
-    // create file
-    let file_name = format!(
-        "{}/{}{}{}",
-        outdir.display(),
-        output,
-        "_telomeric_repeat_windows.",
-        extension
-    );
-    let search_file = File::create(file_name)?;
-    let mut search_file = LineWriter::new(search_file);
-
-    // add headers if extension/file type is a csv
-    if extension == "tsv" {
-        writeln!(
-            search_file,
-            "id\twindow\tforward_repeat_number\treverse_repeat_number\ttelomeric_repeat"
-        )?;
-    }
+    // optionally count approximate matches too
+    let approximate = get_approximate(matches);
 
-    // iterate over the fasta records
-    for result in reader.records() {
-        let record = result?;
-        let id = record.id().to_owned();
+    // all the strings are searched for at once
+    let options = WindowOptions {
+        scanner: utils::MotifScanner::new(&telomeric_repeats)?,
+        telomeric_repeats,
+        window_size,
+        step,
+        approximate,
+    };
 
-        // fn window counter
-        write_window_counts(
-            record,
-            &mut search_file,
-            telomeric_repeat,
-            window_size,
-            id.clone(),
-            extension,
-        )?;
+    // create file(s), with headers
+    let mut search_output = WindowOutput::create(outdir, output, extension, &options)?;
+    // and optionally the telomeric arrays
+    let mut search_arrays = ArrayOutput::create(matches, outdir, output)?;
 
-        eprintln!("[+]\tChromosome {} processed", id);
-    }
+    // iterate over the fasta records, or regions
+    write_records(
+        records,
+        &mut search_output,
+        search_arrays.as_mut(),
+        &options,
+    )?;
+    search_output.finish()?;
     eprintln!("[+]\tFinished searching genome.");
 
     // optional log file
@@ -82,73 +72,1190 @@ pub fn search(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
     Ok(())
 }
 
-/// Iterate over windows, counting occurrences of specified string
-/// and write to file on the fly.
-fn write_window_counts<T: std::io::Write>(
-    sequence: bio::io::fasta::Record,
-    file: &mut LineWriter<T>,
-    telomeric_repeat: &str,
-    window_size: usize,
-    id: String,
+/// Collect the strings to search for, which can be given on the
+/// command line (more than once), and/or in a file with one string
+/// per line. Strings are uppercased, and duplicates removed.
+pub fn get_search_strings(matches: &clap::ArgMatches) -> Result<Vec<String>> {
+    let mut strings: Vec<String> = matches
+        .get_many::<String>("string")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    if let Some(path) = matches.get_one::<PathBuf>("string-file") {
+        let contents = read_to_string(path)?;
+        strings.extend(
+            contents
+                .lines()
+                .map(|line| line.trim())
+                // allow comments and blank lines in the file
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.to_string()),
+        );
+    }
+
+    let mut telomeric_repeats: Vec<String> = Vec::new();
+    for string in strings {
+        let string = utils::validate_dna_string(&string)?;
+        if !telomeric_repeats.contains(&string) {
+            telomeric_repeats.push(string);
+        }
+    }
+
+    if telomeric_repeats.is_empty() {
+        bail!("No search strings were given.")
+    }
+
+    Ok(telomeric_repeats)
+}
+
+/// Telomeric arrays, merged from the motif positions found for the
+/// window counts, and written as GFF3.
+pub(crate) struct ArrayOutput {
+    pub gff: Gff3Writer<File>,
+    merger: ArrayMerger,
+}
+
+impl ArrayOutput {
+    /// Create the GFF3 of telomeric arrays, if `--gff3` was given.
+    pub(crate) fn create(
+        matches: &clap::ArgMatches,
+        outdir: &Path,
+        output: &Path,
+    ) -> Result<Option<Self>> {
+        if !matches.get_flag("gff3") {
+            return Ok(None);
+        }
+        let file_name = format!(
+            "{}/{}{}",
+            outdir.display(),
+            output.display(),
+            "_telomeric_arrays.gff3"
+        );
+        Ok(Some(Self {
+            gff: Gff3Writer::create(file_name)?,
+            merger: ArrayMerger::new(
+                *matches
+                    .get_one::<usize>("max-gap")
+                    .expect("defaulted by clap"),
+                *matches
+                    .get_one::<usize>("min-copies")
+                    .expect("defaulted by clap"),
+            ),
+        }))
+    }
+
+    /// Merge the motif positions found so far into arrays, and write
+    /// those which are done, in order along the sequence.
+    fn write(
+        &mut self,
+        id: &str,
+        counts: &WindowCounts,
+        telomeric_repeats: &[String],
+        offset: usize,
+        finished: bool,
+    ) -> Result<()> {
+        for (array, i) in self.merger.merge(counts, telomeric_repeats, finished) {
+            self.gff
+                .write_array(id, &telomeric_repeats[i], &array, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Merges the motif positions of the window counts into telomeric
+/// arrays, as `tidk call` does, as a sequence is counted.
+pub(crate) struct ArrayMerger {
+    /// Motif hits up to this many bases apart are merged.
+    max_gap: usize,
+    /// Arrays with fewer copies are dropped.
+    min_copies: usize,
+    /// The motif positions on either strand of each telomeric repeat
+    /// which might still be merged with those later in the sequence.
+    pending: Vec<[Vec<usize>; 2]>,
+    /// The arrays merged, and the index of their telomeric repeat, which
+    /// are waiting for the arrays before them to be merged.
+    merged: Vec<(call::TelomericArray, usize)>,
+}
+
+impl ArrayMerger {
+    pub(crate) fn new(max_gap: usize, min_copies: usize) -> Self {
+        Self {
+            max_gap,
+            min_copies,
+            pending: Vec::new(),
+            merged: Vec::new(),
+        }
+    }
+
+    /// Merge the (non-overlapping) forward and reverse motif positions
+    /// found so far into arrays, and return those which are done, with
+    /// the index of their telomeric repeat, in order along the sequence.
+    /// The last hits on each strand might still be merged with hits to
+    /// come, so they're kept until the sequence is `finished`, as are
+    /// the arrays after them.
+    pub(crate) fn merge(
+        &mut self,
+        counts: &WindowCounts,
+        telomeric_repeats: &[String],
+        finished: bool,
+    ) -> Vec<(call::TelomericArray, usize)> {
+        self.pending
+            .resize_with(telomeric_repeats.len(), Default::default);
+        for (i, (telomeric_repeat, repeat_counts)) in
+            telomeric_repeats.iter().zip(&counts.repeats).enumerate()
+        {
+            let length = telomeric_repeat.len();
+            // palindromic repeats would otherwise be written twice
+            let palindromic = utils::reverse_complement(telomeric_repeat) == *telomeric_repeat;
+            let [forward, reverse] = &mut self.pending[i];
+            let strands = [
+                (
+                    forward,
+                    &repeat_counts.forward_positions,
+                    call::Strand::Forward,
+                ),
+                (
+                    reverse,
+                    &repeat_counts.reverse_positions,
+                    call::Strand::Reverse,
+                ),
+            ];
+            for (pending, positions, strand) in strands.into_iter().take(2 - palindromic as usize) {
+                pending.extend(positions);
+                // a hit is merged with the one before it if close enough,
+                // so only the last run of hits can grow
+                let done = if finished {
+                    pending.len()
+                } else {
+                    (1..pending.len())
+                        .rev()
+                        .find(|&j| pending[j] > pending[j - 1] + length + self.max_gap)
+                        .unwrap_or(0)
+                };
+                let arrays = call::merge_hits(
+                    &pending[..done],
+                    length,
+                    self.max_gap,
+                    self.min_copies,
+                    strand,
+                );
+                pending.drain(..done);
+                self.merged
+                    .extend(arrays.into_iter().map(|array| (array, i)));
+            }
+        }
+
+        // the hits to come are after those so far, so the arrays merged
+        // are done up to the first hit which is still pending
+        let pending_from = self
+            .pending
+            .iter()
+            .flatten()
+            .filter_map(|pending| pending.first())
+            .min();
+        let written_to = match pending_from {
+            Some(start) if !finished => *start,
+            _ => usize::MAX,
+        };
+        self.merged
+            .sort_by_key(|(array, i)| (array.start, array.end, *i));
+        let ready = self
+            .merged
+            .partition_point(|(array, _)| array.start < written_to);
+        self.merged.drain(..ready).collect()
+    }
+}
+
+/// Whether to count approximate matches, and how. `--mismatches` and
+/// `--edit-distance` are mutually exclusive.
+pub fn get_approximate(matches: &clap::ArgMatches) -> Option<utils::Approximate> {
+    if let Some(k) = matches.get_one::<usize>("mismatches") {
+        Some(utils::Approximate::Mismatches(*k))
+    } else {
+        matches
+            .get_one::<usize>("edit-distance")
+            .map(|k| utils::Approximate::EditDistance(*k))
+    }
+}
+
+/// The distance between the starts of consecutive windows, which
+/// defaults to the window size (i.e. windows which don't overlap).
+pub fn get_step(matches: &clap::ArgMatches, window_size: usize) -> Result<usize> {
+    let step = matches
+        .get_one::<usize>("step")
+        .copied()
+        .unwrap_or(window_size);
+    if step == 0 || window_size == 0 {
+        bail!("The window size and step must be greater than zero.")
+    }
+    Ok(step)
+}
+
+/// The names of the output files of `tidk search` and `tidk find`.
+/// Bedgraph and bigwig output have a file for each strand of each
+/// telomeric repeat, named after the repeat, forward then reverse.
+pub(crate) fn window_file_names(
+    outdir: &Path,
+    output: &Path,
     extension: &str,
-) -> Result<()> {
-    // get forward and reverse sequences, and length
-    // to remove overlapping matches.
-    let forward_telomeric_seq = telomeric_repeat.to_uppercase();
-    let reverse_telomeric_seq = utils::reverse_complement(&forward_telomeric_seq).to_uppercase();
-    let telomeric_length = forward_telomeric_seq.len();
+    telomeric_repeats: &[String],
+) -> Vec<String> {
+    let prefix = format!(
+        "{}/{}_telomeric_repeat_windows",
+        outdir.display(),
+        output.display()
+    );
+    let suffix = match extension {
+        "bedgraph" => "bedgraph",
+        "bigwig" => "bw",
+        _ => return vec![format!("{}.{}", prefix, extension)],
+    };
+    telomeric_repeats
+        .iter()
+        .flat_map(|telomeric_repeat| {
+            ["forward", "reverse"]
+                .map(|strand| format!("{}_{}_{}.{}", prefix, telomeric_repeat, strand, suffix))
+        })
+        .collect()
+}
 
-    // create the iterator in each loop iteration isnt costly is it?
-    let windows = sequence.seq().chunks(window_size);
-    // keep track of the window size
+/// Where the window counts are written, depending on the extension.
+pub(crate) enum WindowOutput<T: std::io::Write> {
+    /// A TSV, with a row per window per string.
+    Tsv(LineWriter<T>),
+    /// A bedGraph track for each strand of each string, so the
+    /// orientation of the repeats at each end can be seen in a genome
+    /// browser.
+    BedGraph(Vec<Strands<LineWriter<T>>>),
+    /// As for bedGraph, but written as BigWig.
+    BigWig(Vec<Strands<BigWigWriter>>),
+}
 
-    let mut start = 0;
-    let mut end = window_size;
+/// The forward and reverse tracks of a string.
+pub(crate) struct Strands<W> {
+    pub(crate) forward: W,
+    pub(crate) reverse: W,
+}
 
-    // iterate over windows
-    for (i, window) in windows.enumerate() {
-        // make window uppercase
-        let windows_upper = str::from_utf8(window)?.to_uppercase();
-        // for each window, find the motifs in this
-        let forward_motif = utils::find_motifs(&forward_telomeric_seq, &windows_upper);
-        let reverse_motif = utils::find_motifs(&reverse_telomeric_seq, &windows_upper);
-
-        // remove overlapping matches
-        // not sure this is necessary, but thought it might be...
-        let forward_motif_noverlap =
-            utils::remove_overlapping_indexes(forward_motif, telomeric_length);
-        let reverse_motif_noverlap =
-            utils::remove_overlapping_indexes(reverse_motif, telomeric_length);
-
-        // the number of matches for forward/reverse
-        let forward_repeat_number = forward_motif_noverlap.len();
-        let reverse_repeat_number = reverse_motif_noverlap.len();
-        // write to file
-        // increment window
-        if i != 0 {
-            start += window_size;
-            end += window_size;
-        }
-        if end > sequence.seq().len() {
-            end = sequence.seq().len();
+impl<T: std::io::Write> WindowOutput<T> {
+    /// Flush the text output, or write the indexes of the BigWig files.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            WindowOutput::Tsv(mut file) => file.flush()?,
+            WindowOutput::BedGraph(tracks) => {
+                for mut strands in tracks {
+                    strands.forward.flush()?;
+                    strands.reverse.flush()?;
+                }
+            }
+            WindowOutput::BigWig(tracks) => {
+                for strands in tracks {
+                    strands.forward.finish()?;
+                    strands.reverse.finish()?;
+                }
+            }
         }
+        Ok(())
+    }
+}
+
+impl WindowOutput<File> {
+    /// Create the output file(s), and write the TSV header, or the
+    /// bedGraph track lines.
+    pub(crate) fn create(
+        outdir: &Path,
+        output: &Path,
+        extension: &str,
+        options: &WindowOptions,
+    ) -> Result<Self> {
+        let file_names = window_file_names(outdir, output, extension, &options.telomeric_repeats);
 
         if extension == "tsv" {
-            writeln!(
-                file,
-                "{}\t{}\t{}\t{}\t{}",
-                id, end, forward_repeat_number, reverse_repeat_number, forward_telomeric_seq
+            let mut file = LineWriter::new(File::create(&file_names[0])?);
+            writeln!(file, "{}", tsv_header(options.approximate))?;
+            return Ok(WindowOutput::Tsv(file));
+        }
+
+        // a bedgraph or bigwig track has a single value per window
+        if options.step < options.window_size {
+            bail!("Bedgraph and bigwig windows can't overlap, so the step must be at least the window size.")
+        }
+        if options.approximate.is_some() {
+            bail!("Bedgraph and bigwig output have no track for the approximate counts, so use TSV output with --edit-distance or --mismatches.")
+        }
+        if extension == "bigwig" {
+            let tracks = file_names
+                .chunks(2)
+                .map(|file_names| {
+                    Ok(Strands {
+                        forward: BigWigWriter::create(&file_names[0])?,
+                        reverse: BigWigWriter::create(&file_names[1])?,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            return Ok(WindowOutput::BigWig(tracks));
+        }
+
+        // the forward and reverse files of each string
+        let mut tracks = Vec::new();
+        for (telomeric_repeat, file_names) in
+            options.telomeric_repeats.iter().zip(file_names.chunks(2))
+        {
+            let mut files = Vec::new();
+            for (file_name, strand, colour) in [
+                (&file_names[0], "forward", "0,0,200"),
+                (&file_names[1], "reverse", "200,0,0"),
+            ] {
+                let mut file = LineWriter::new(File::create(file_name)?);
+                writeln!(
+                    file,
+                    "track type=bedGraph name=\"{} {} {}\" description=\"{} repeats per {} bp window, {} strand\" visibility=full color={}",
+                    output.display(),
+                    telomeric_repeat,
+                    strand,
+                    telomeric_repeat,
+                    options.window_size,
+                    strand,
+                    colour
+                )?;
+                files.push(file);
+            }
+            let reverse = files.pop().expect("two files");
+            let forward = files.pop().expect("two files");
+            tracks.push(Strands { forward, reverse });
+        }
+
+        Ok(WindowOutput::BedGraph(tracks))
+    }
+}
+
+/// The header of the TSV output. The approximate counts are only
+/// added as extra columns if they were asked for.
+pub(crate) fn tsv_header(approximate: Option<utils::Approximate>) -> String {
+    let mut header =
+        "id\twindow\tforward_repeat_number\treverse_repeat_number\ttelomeric_repeat".to_string();
+    if approximate.is_some() {
+        header += "\tforward_approximate_repeat_number\treverse_approximate_repeat_number";
+    }
+    header += "\twindow_start\twindow_length\trepeat_bases\trepeat_fraction\tn_content";
+    header
+}
+
+/// The `(start, end)` of each window along a sequence. Windows start
+/// every `step` bases, so overlap if the step is less than the window
+/// size, and stop at the first window reaching the end of the sequence.
+fn windows(seq_len: usize, window_size: usize, step: usize) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < seq_len {
+        let end = (start + window_size).min(seq_len);
+        windows.push((start, end));
+        if end == seq_len {
+            break;
+        }
+        start += step;
+    }
+    windows
+}
+
+/// Count the motif matches which fall entirely within each window,
+/// given the `(start, end)` of the matches, sorted by both start and
+/// end. The windows are also sorted, so rather than rescanning each
+/// window, the first and last matches in the window are moved along.
+fn count_in_windows(matches: &[(usize, usize)], windows: &[(usize, usize)]) -> Vec<usize> {
+    // the first match starting in, and the first match ending after, the window
+    let (mut first, mut last) = (0, 0);
+
+    windows
+        .iter()
+        .map(|(start, end)| {
+            while first < matches.len() && matches[first].0 < *start {
+                first += 1;
+            }
+            while last < matches.len() && matches[last].1 <= *end {
+                last += 1;
+            }
+            last.saturating_sub(first)
+        })
+        .collect()
+}
+
+/// The bases covered by the motif matches which fall entirely within
+/// each window, so a match is counted in the bases of the same windows
+/// as in `count_in_windows`. The matches are sorted as for
+/// `count_in_windows`, and may overlap each other.
+fn bases_in_windows(matches: &[(usize, usize)], windows: &[(usize, usize)]) -> Vec<usize> {
+    let (mut first, mut last) = (0, 0);
+
+    windows
+        .iter()
+        .map(|(start, end)| {
+            while first < matches.len() && matches[first].0 < *start {
+                first += 1;
+            }
+            while last < matches.len() && matches[last].1 <= *end {
+                last += 1;
+            }
+            // the union of the matches in the window
+            let (mut bases, mut covered_to) = (0, 0);
+            for (match_start, match_end) in &matches[first..last.max(first)] {
+                bases += match_end.saturating_sub(*match_start.max(&covered_to));
+                covered_to = covered_to.max(*match_end);
+            }
+            bases
+        })
+        .collect()
+}
+
+/// The bases covered by a set of intervals, so the number of bases
+/// covered in any window can be found without rescanning the window.
+struct Coverage {
+    /// Disjoint `(start, end)` intervals, sorted by start.
+    intervals: Vec<(usize, usize)>,
+    /// The bases covered by the intervals before each interval.
+    cumulative: Vec<usize>,
+}
+
+impl Coverage {
+    /// Merge the (possibly overlapping) intervals, which need not
+    /// be sorted.
+    fn new(mut intervals: Vec<(usize, usize)>) -> Self {
+        intervals.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in intervals {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut cumulative = Vec::with_capacity(merged.len());
+        let mut total = 0;
+        for (start, end) in &merged {
+            cumulative.push(total);
+            total += end - start;
+        }
+        Self {
+            intervals: merged,
+            cumulative,
+        }
+    }
+
+    /// The bases covered before `position`.
+    fn covered_before(&self, position: usize) -> usize {
+        // the number of intervals starting before the position
+        let i = self
+            .intervals
+            .partition_point(|(start, _)| *start < position);
+        if i == 0 {
+            return 0;
+        }
+        let (start, end) = self.intervals[i - 1];
+        self.cumulative[i - 1] + end.min(position) - start
+    }
+
+    /// The bases covered in the window `[start, end)`.
+    fn covered(&self, start: usize, end: usize) -> usize {
+        self.covered_before(end) - self.covered_before(start)
+    }
+}
+
+/// The runs of `N` (or `n`) bases in a sequence.
+fn n_runs(sequence: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut run_start = None;
+    for (i, base) in sequence.iter().enumerate() {
+        match (base.eq_ignore_ascii_case(&b'N'), run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                runs.push((start, i));
+                run_start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(start) = run_start {
+        runs.push((start, sequence.len()));
+    }
+    runs
+}
+
+/// The options for counting telomeric repeats in windows, shared
+/// by `tidk search` and `tidk find`.
+pub(crate) struct WindowOptions {
+    /// Finds all of the telomeric repeats in one pass.
+    pub scanner: utils::MotifScanner,
+    /// The telomeric repeats, in the order given to the scanner.
+    pub telomeric_repeats: Vec<String>,
+    /// The size of the windows.
+    pub window_size: usize,
+    /// The distance between the starts of consecutive windows.
+    pub step: usize,
+    /// Whether to also count approximate matches.
+    pub approximate: Option<utils::Approximate>,
+}
+
+/// The number of bases in each block of windows which are counted in
+/// parallel, within a sequence.
+pub(crate) const BLOCK_SIZE: usize = 1_000_000;
+/// The number of blocks of a sequence to read before counting them.
+const CHUNK_BLOCKS: usize = 16;
+/// The number of bases read from a sequence at a time. Longer sequences
+/// are counted as they're read, rather than being read whole.
+pub(crate) const CHUNK_SIZE: usize = CHUNK_BLOCKS * BLOCK_SIZE;
+/// The number of bases of (shorter) sequences to read before counting
+/// them in parallel, which bounds the memory used.
+const BATCH_SIZE: usize = 100_000_000;
+
+/// Count the windows of the sequences (or regions), and write them in
+/// the order they were read. Sequences shorter than a chunk are read
+/// whole and counted in parallel, a batch at a time. Longer ones are
+/// counted and written a chunk at a time as they're read, so the memory
+/// used doesn't grow with the length of the chromosomes.
+pub(crate) fn write_records<T: std::io::Write>(
+    mut records: reader::SequenceReader,
+    output: &mut WindowOutput<T>,
+    mut arrays: Option<&mut ArrayOutput>,
+    options: &WindowOptions,
+) -> Result<()> {
+    let mut batch = Vec::new();
+    let mut batch_bases = 0;
+    while let Some(header) = records.next_sequence()? {
+        let mut sequence = Vec::new();
+        if records.read(&mut sequence, CHUNK_SIZE)? < CHUNK_SIZE {
+            batch_bases += sequence.len();
+            batch.push((header, sequence));
+            if batch_bases >= BATCH_SIZE {
+                write_batch(&mut batch, output, arrays.as_deref_mut(), options)?;
+                batch_bases = 0;
+            }
+            continue;
+        }
+
+        // the batch so far comes first
+        write_batch(&mut batch, output, arrays.as_deref_mut(), options)?;
+        batch_bases = 0;
+
+        let mut counter = WindowCounter::new(options, BLOCK_SIZE);
+        let mut length = 0;
+        loop {
+            length += sequence.len();
+            counter.push(&sequence);
+            write_sequence(
+                &header,
+                &counter.take(),
+                output,
+                arrays.as_deref_mut(),
+                options,
+                None,
             )?;
+            sequence.clear();
+            if records.read(&mut sequence, CHUNK_SIZE)? < CHUNK_SIZE {
+                length += sequence.len();
+                counter.push(&sequence);
+                break;
+            }
+        }
+        write_sequence(
+            &header,
+            &counter.finish(),
+            output,
+            arrays.as_deref_mut(),
+            options,
+            Some(length),
+        )?;
+    }
+    write_batch(&mut batch, output, arrays, options)
+}
+
+/// Count a batch of whole sequences in parallel, then write them in
+/// the order they were read.
+fn write_batch<T: std::io::Write>(
+    batch: &mut Vec<(reader::SequenceHeader, Vec<u8>)>,
+    output: &mut WindowOutput<T>,
+    mut arrays: Option<&mut ArrayOutput>,
+    options: &WindowOptions,
+) -> Result<()> {
+    let counts: Vec<WindowCounts> = batch
+        .par_iter()
+        .map(|(_, sequence)| count_windows(sequence, options, BLOCK_SIZE))
+        .collect();
+    for ((header, sequence), counts) in batch.drain(..).zip(counts) {
+        write_sequence(
+            &header,
+            &counts,
+            output,
+            arrays.as_deref_mut(),
+            options,
+            Some(sequence.len()),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the counts of a sequence, or of the windows counted so far
+/// until it's finished and the `length` read is known, shifted to
+/// chromosome coordinates if only a region of it was read. The motif
+/// positions can also be merged into telomeric arrays.
+fn write_sequence<T: std::io::Write>(
+    header: &reader::SequenceHeader,
+    counts: &WindowCounts,
+    output: &mut WindowOutput<T>,
+    arrays: Option<&mut ArrayOutput>,
+    options: &WindowOptions,
+    length: Option<usize>,
+) -> Result<()> {
+    let offset = header.region.as_ref().map_or(0, |region| region.start);
+    write_counts(counts, output, options, &header.id, offset)?;
+    if let Some(arrays) = arrays {
+        arrays.write(
+            &header.id,
+            counts,
+            &options.telomeric_repeats,
+            offset,
+            length.is_some(),
+        )?;
+    }
+    let Some(length) = length else {
+        return Ok(());
+    };
+    // the BigWig needs the length of the whole chromosome
+    if let WindowOutput::BigWig(tracks) = output {
+        let length = header
+            .region
+            .as_ref()
+            .map_or(length, |region| region.length);
+        for strands in tracks {
+            strands.forward.set_length(&header.id, length)?;
+            strands.reverse.set_length(&header.id, length)?;
+        }
+    }
+    match &header.region {
+        Some(region) => eprintln!("[+]\tRegion {} processed", region),
+        None => eprintln!("[+]\tChromosome {} processed", header.id),
+    }
+    Ok(())
+}
+
+/// The counts in each window of a sequence.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct WindowCounts {
+    /// The `(start, end)` of each window.
+    windows: Vec<(usize, usize)>,
+    /// The bases in gaps (runs of N) in each window.
+    gap_bases: Vec<usize>,
+    /// The counts of each telomeric repeat, in order.
+    repeats: Vec<RepeatCounts>,
+}
+
+/// The counts of a telomeric repeat in each window, and the starts of
+/// its matches, to merge into telomeric arrays.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RepeatCounts {
+    forward: Vec<usize>,
+    reverse: Vec<usize>,
+    /// The approximate matches on either strand, if asked for.
+    approximate: Option<(Vec<usize>, Vec<usize>)>,
+    /// The bases covered by the exact matches on either strand.
+    repeat_bases: Vec<usize>,
+    forward_positions: Vec<usize>,
+    reverse_positions: Vec<usize>,
+}
+
+/// The `(start, end, distance)` of approximate matches.
+type ApproximateMatches = Vec<(usize, usize, usize)>;
+
+/// The counts of a block of windows, along with all of the approximate
+/// matches starting in the block, for each telomeric repeat and strand.
+/// Which approximate matches overlap depends on the matches before, so
+/// they are only counted once the blocks before are done.
+struct Block {
+    counts: WindowCounts,
+    approximate: Vec<[ApproximateMatches; 2]>,
+}
+
+impl WindowCounts {
+    /// Remove the first `n` windows, along with all of the motif
+    /// positions.
+    fn drain(&mut self, n: usize) -> WindowCounts {
+        WindowCounts {
+            windows: self.windows.drain(..n).collect(),
+            gap_bases: self.gap_bases.drain(..n).collect(),
+            repeats: self
+                .repeats
+                .iter_mut()
+                .map(|counts| RepeatCounts {
+                    forward: counts.forward.drain(..n).collect(),
+                    reverse: counts.reverse.drain(..n).collect(),
+                    approximate: counts.approximate.as_mut().map(|(forward, reverse)| {
+                        (forward.drain(..n).collect(), reverse.drain(..n).collect())
+                    }),
+                    repeat_bases: counts.repeat_bases.drain(..n).collect(),
+                    forward_positions: std::mem::take(&mut counts.forward_positions),
+                    reverse_positions: std::mem::take(&mut counts.reverse_positions),
+                })
+                .collect(),
+        }
+    }
+
+    /// Add the counts of the next block of windows.
+    fn append(&mut self, mut block: WindowCounts) {
+        self.windows.append(&mut block.windows);
+        self.gap_bases.append(&mut block.gap_bases);
+        for (counts, mut block) in self.repeats.iter_mut().zip(block.repeats) {
+            counts.forward.append(&mut block.forward);
+            counts.reverse.append(&mut block.reverse);
+            counts.repeat_bases.append(&mut block.repeat_bases);
+            counts
+                .forward_positions
+                .append(&mut block.forward_positions);
+            counts
+                .reverse_positions
+                .append(&mut block.reverse_positions);
+        }
+    }
+}
+
+/// Count occurrences of each of the telomeric repeats in windows along
+/// a whole sequence, with blocks of about `block_size` bases counted in
+/// parallel (see [`WindowCounter`]).
+pub(crate) fn count_windows(
+    sequence: &[u8],
+    options: &WindowOptions,
+    block_size: usize,
+) -> WindowCounts {
+    let mut counter = WindowCounter::new(options, block_size);
+    counter.push(sequence);
+    counter.finish()
+}
+
+/// Counts occurrences of each of the telomeric repeats in windows along
+/// a sequence as it's read, a chunk at a time. Only the sequence for the
+/// windows which haven't been counted yet is kept, along with enough
+/// either side to find the matches overlapping them, including those
+/// spanning the chunks.
+///
+/// The windows of a chunk are split into blocks of about `block_size`
+/// bases which are counted in parallel. Each block is scanned along
+/// with enough sequence either side too, so the counts depend on neither
+/// the chunks nor the blocks.
+pub(crate) struct WindowCounter<'a> {
+    options: &'a WindowOptions,
+    block_size: usize,
+    /// The longest match, including indels.
+    margin: usize,
+    /// The sequence kept, and where it starts in the whole sequence.
+    buffer: Vec<u8>,
+    buffer_start: usize,
+    /// The index of the next window to count.
+    next_window: usize,
+    /// Where the matches which haven't been kept yet start.
+    core_start: usize,
+    counts: WindowCounts,
+    /// Counts the approximate matches of each telomeric repeat, on
+    /// either strand.
+    approximate: Vec<[ApproximateCounter; 2]>,
+}
+
+impl<'a> WindowCounter<'a> {
+    pub(crate) fn new(options: &'a WindowOptions, block_size: usize) -> Self {
+        let longest = options
+            .telomeric_repeats
+            .iter()
+            .map(|t| t.len())
+            .max()
+            .unwrap_or(0);
+        // the edit distance is at most the length of the motif
+        let margin = longest
+            + match options.approximate {
+                Some(utils::Approximate::EditDistance(k)) => k.min(longest),
+                _ => 0,
+            };
+        let repeats = options
+            .telomeric_repeats
+            .iter()
+            .map(|_| RepeatCounts {
+                approximate: options.approximate.map(|_| Default::default()),
+                ..Default::default()
+            })
+            .collect();
+
+        Self {
+            options,
+            block_size,
+            margin,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            next_window: 0,
+            core_start: 0,
+            counts: WindowCounts {
+                repeats,
+                ..Default::default()
+            },
+            approximate: options
+                .telomeric_repeats
+                .iter()
+                .map(|_| Default::default())
+                .collect(),
+        }
+    }
+
+    /// Add the next part of the sequence, and count the windows which
+    /// are complete once there are enough bases for a chunk.
+    pub(crate) fn push(&mut self, sequence: &[u8]) {
+        self.buffer.extend_from_slice(sequence);
+        if self.buffer.len() >= self.block_size.saturating_mul(CHUNK_BLOCKS) {
+            self.count(false);
+        }
+    }
+
+    /// Take the windows counted so far, so they can be written before the
+    /// rest of the sequence is added. The approximate counts can lag
+    /// behind, so windows are only taken once those are done too.
+    pub(crate) fn take(&mut self) -> WindowCounts {
+        let done = self
+            .counts
+            .repeats
+            .iter()
+            .filter_map(|counts| counts.approximate.as_ref())
+            .map(|(forward, reverse)| forward.len().min(reverse.len()))
+            .fold(self.counts.windows.len(), usize::min);
+        self.counts.drain(done)
+    }
+
+    /// Count the windows left, once all of the sequence has been added.
+    pub(crate) fn finish(mut self) -> WindowCounts {
+        self.count(true);
+        self.counts
+    }
+
+    /// Count the windows which end at least a margin before the end of
+    /// the sequence kept, or all of the windows left once the sequence is
+    /// `finished`. Then drop the sequence which isn't needed any more.
+    fn count(&mut self, finished: bool) {
+        let (window_size, step, margin) =
+            (self.options.window_size, self.options.step, self.margin);
+        let buffer_end = self.buffer_start + self.buffer.len();
+
+        // windows start every step, so the windows left are those of
+        // the rest of the sequence
+        let first_start = self.next_window * step;
+        let windows: Vec<(usize, usize)> = if finished {
+            windows(buffer_end.saturating_sub(first_start), window_size, step)
         } else {
-            // for bedgraph only four columns, and sum the forward & reverse for convenience
-            writeln!(
-                file,
-                "{}\t{}\t{}\t{}",
-                id,
-                start,
-                end,
-                forward_repeat_number + reverse_repeat_number,
-            )?;
+            let available = buffer_end.saturating_sub(margin);
+            windows(available.saturating_sub(first_start), window_size, step)
+                .into_iter()
+                .take_while(|(start, end)| end - start == window_size)
+                .collect()
+        }
+        .into_iter()
+        .map(|(start, end)| (start + first_start, end + first_start))
+        .collect();
+        if windows.is_empty() && !finished {
+            return;
+        }
+
+        // the matches starting between here and the next window are kept
+        let next_window = self.next_window + windows.len();
+        let core_end = if finished {
+            buffer_end
+        } else {
+            (next_window * step).min(buffer_end - margin)
+        };
+        let blocks: Vec<&[(usize, usize)]> = if windows.is_empty() {
+            vec![&[]]
+        } else {
+            windows.chunks((self.block_size / step).max(1)).collect()
+        };
+
+        let blocks: Vec<Block> = blocks
+            .par_iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let block_start = if i == 0 { self.core_start } else { block[0].0 };
+                let block_end = blocks.get(i + 1).map_or(core_end, |next| next[0].0);
+                let slice_start = block_start.saturating_sub(margin);
+                let slice_end =
+                    (block_end.max(block.last().map_or(0, |w| w.1)) + margin).min(buffer_end);
+                count_block(
+                    &self.buffer[slice_start - self.buffer_start..slice_end - self.buffer_start],
+                    slice_start,
+                    block,
+                    (block_start, block_end),
+                    self.options,
+                )
+            })
+            .collect();
+
+        for block in blocks {
+            self.counts.append(block.counts);
+            for (counters, matches) in self.approximate.iter_mut().zip(block.approximate) {
+                for (counter, matches) in counters.iter_mut().zip(matches) {
+                    counter.found.extend(matches);
+                }
+            }
+        }
+
+        // all of the approximate matches ending before the end of the
+        // core are found, which is all of those starting in the windows
+        // ending at least a margin before
+        let found_to = if finished { usize::MAX } else { core_end };
+        let next_start = next_window * step;
+        let WindowCounts {
+            windows, repeats, ..
+        } = &mut self.counts;
+        for (repeat_counts, [forward, reverse]) in repeats.iter_mut().zip(&mut self.approximate) {
+            if let Some((forward_counts, reverse_counts)) = &mut repeat_counts.approximate {
+                forward.count(found_to, margin, windows, next_start, forward_counts);
+                reverse.count(found_to, margin, windows, next_start, reverse_counts);
+            }
+        }
+
+        self.next_window = next_window;
+        self.core_start = core_end;
+        let keep_from = core_end.saturating_sub(margin).max(self.buffer_start);
+        self.buffer.drain(..keep_from - self.buffer_start);
+        self.buffer_start = keep_from;
+    }
+}
+
+/// Counts the non-overlapping approximate matches of a telomeric repeat
+/// on one strand in windows, as the sequence is counted.
+#[derive(Default)]
+struct ApproximateCounter {
+    /// The matches found which haven't been picked from yet, as there
+    /// might be matches ending before them still to find.
+    found: ApproximateMatches,
+    picked: utils::NonOverlappingMatches,
+}
+
+impl ApproximateCounter {
+    /// Pick from the matches found which end by `found_to`, as all of the
+    /// matches ending before then have been found. Then count them in the
+    /// windows which are at least a `margin` before, where the matches
+    /// can't change, adding to the `counts` for the windows so far. The
+    /// next window, after the `windows`, starts at `next_start`.
+    fn count(
+        &mut self,
+        found_to: usize,
+        margin: usize,
+        windows: &[(usize, usize)],
+        next_start: usize,
+        counts: &mut Vec<usize>,
+    ) {
+        // edit distance matches starting in one block can end after
+        // those starting in the next
+        self.found.sort_by_key(|(_, end, _)| *end);
+        let ready = self.found.partition_point(|(_, end, _)| *end <= found_to);
+        for found in self.found.drain(..ready) {
+            self.picked.push(found);
+        }
+
+        let windows = &windows[counts.len()..];
+        let ready = windows
+            .iter()
+            .take_while(|(_, end)| end.saturating_add(margin) <= found_to)
+            .count();
+        let picked: Vec<(usize, usize)> = self
+            .picked
+            .matches()
+            .iter()
+            .map(|(start, end, _)| (*start, *end))
+            .collect();
+        counts.extend(count_in_windows(&picked, &windows[..ready]));
+
+        // the matches starting before the next window to count aren't
+        // needed again
+        let next_start = windows.get(ready).map_or(next_start, |w| w.0);
+        self.picked.forget_before(next_start);
+    }
+}
+
+/// Count the telomeric repeats in a block of windows, given the `slice`
+/// of the sequence around them, which starts at `slice_start`. Only the
+/// matches starting in the `core` of the block are kept for the arrays,
+/// and the approximate matches.
+fn count_block(
+    slice: &[u8],
+    slice_start: usize,
+    block: &[(usize, usize)],
+    core: (usize, usize),
+    options: &WindowOptions,
+) -> Block {
+    let WindowOptions {
+        scanner,
+        telomeric_repeats,
+        approximate,
+        ..
+    } = options;
+
+    // the windows in the coordinates of the slice
+    let windows: Vec<(usize, usize)> = block
+        .iter()
+        .map(|(start, end)| (start - slice_start, end - slice_start))
+        .collect();
+    let motifs = scanner.find_all(slice);
+    // the gaps in the assembly, for the N content of each window
+    let gaps = Coverage::new(n_runs(slice));
+    let in_core = |i: &usize| (core.0..core.1).contains(i);
+    let mut approximate_matches = Vec::new();
+
+    let repeats = telomeric_repeats
+        .iter()
+        .zip(motifs)
+        .map(|(telomeric_repeat, (forward_motif, reverse_motif))| {
+            // length to remove overlapping matches.
+            let telomeric_length = telomeric_repeat.len();
+
+            // remove overlapping matches
+            // not sure this is necessary, but thought it might be...
+            let forward_motif_noverlap =
+                utils::remove_overlapping_indexes(forward_motif, telomeric_length);
+            let reverse_motif_noverlap =
+                utils::remove_overlapping_indexes(reverse_motif, telomeric_length);
+
+            let forward_matches: Vec<(usize, usize)> = forward_motif_noverlap
+                .iter()
+                .map(|i| (*i, i + telomeric_length))
+                .collect();
+            let reverse_matches: Vec<(usize, usize)> = reverse_motif_noverlap
+                .iter()
+                .map(|i| (*i, i + telomeric_length))
+                .collect();
+
+            // the number of matches for forward/reverse in each window
+            let forward = count_in_windows(&forward_matches, &windows);
+            let reverse = count_in_windows(&reverse_matches, &windows);
+            // and the bases covered by the (exact) matches on either strand,
+            // which have the same length, so are sorted by both start and end
+            let mut matches = [forward_matches, reverse_matches].concat();
+            matches.sort_unstable();
+            let repeat_bases = bases_in_windows(&matches, &windows);
+
+            // and the approximate matches, if asked for
+            if let Some(approximate) = approximate {
+                let find = |motif: &str| -> ApproximateMatches {
+                    utils::find_all_approximate_motifs(motif, slice, *approximate)
+                        .into_iter()
+                        .map(|(start, end, distance)| {
+                            (start + slice_start, end + slice_start, distance)
+                        })
+                        .filter(|(start, _, _)| in_core(start))
+                        .collect()
+                };
+                approximate_matches.push([
+                    find(telomeric_repeat),
+                    find(&utils::reverse_complement(telomeric_repeat)),
+                ]);
+            }
+
+            RepeatCounts {
+                forward,
+                reverse,
+                approximate: None,
+                repeat_bases,
+                forward_positions: forward_motif_noverlap
+                    .iter()
+                    .map(|i| i + slice_start)
+                    .filter(in_core)
+                    .collect(),
+                reverse_positions: reverse_motif_noverlap
+                    .iter()
+                    .map(|i| i + slice_start)
+                    .filter(in_core)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Block {
+        counts: WindowCounts {
+            windows: block.to_vec(),
+            gap_bases: windows
+                .iter()
+                .map(|(start, end)| gaps.covered(*start, *end))
+                .collect(),
+            repeats,
+        },
+        approximate: approximate_matches,
+    }
+}
+
+/// Write the window counts of (part of) a sequence, with a row per
+/// window per string, the strings' rows for each window together, so
+/// the windows can be written as they're counted. The `offset` of the
+/// sequence in its chromosome is added to the window coordinates, for
+/// when only a region of the chromosome was read.
+pub(crate) fn write_counts<T: std::io::Write>(
+    counts: &WindowCounts,
+    output: &mut WindowOutput<T>,
+    options: &WindowOptions,
+    id: &str,
+    offset: usize,
+) -> Result<()> {
+    // iterate over windows
+    for (i, (window_start, window_end)) in counts.windows.iter().copied().enumerate() {
+        let (start, end) = (window_start + offset, window_end + offset);
+        for (r, (telomeric_repeat, repeat_counts)) in options
+            .telomeric_repeats
+            .iter()
+            .zip(&counts.repeats)
+            .enumerate()
+        {
+            let forward_repeat_number = repeat_counts.forward[i];
+            let reverse_repeat_number = repeat_counts.reverse[i];
+
+            // write to file
+            match output {
+                WindowOutput::Tsv(file) => {
+                    write!(
+                        file,
+                        "{}\t{}\t{}\t{}\t{}",
+                        id, end, forward_repeat_number, reverse_repeat_number, telomeric_repeat
+                    )?;
+                    if let Some((forward_approximate, reverse_approximate)) =
+                        &repeat_counts.approximate
+                    {
+                        write!(
+                            file,
+                            "\t{}\t{}",
+                            forward_approximate[i], reverse_approximate[i]
+                        )?;
+                    }
+                    // normalised by the window length, as the last window is shorter
+                    let window_length = window_end - window_start;
+                    let repeat_bases = repeat_counts.repeat_bases[i];
+                    writeln!(
+                        file,
+                        "\t{}\t{}\t{}\t{:.4}\t{:.4}",
+                        start,
+                        window_length,
+                        repeat_bases,
+                        repeat_bases as f64 / window_length as f64,
+                        counts.gap_bases[i] as f64 / window_length as f64
+                    )?;
+                }
+                WindowOutput::BedGraph(tracks) => {
+                    // a track for each strand of each string
+                    let Strands { forward, reverse } = &mut tracks[r];
+                    writeln!(
+                        forward,
+                        "{}\t{}\t{}\t{}",
+                        id, start, end, forward_repeat_number
+                    )?;
+                    writeln!(
+                        reverse,
+                        "{}\t{}\t{}\t{}",
+                        id, start, end, reverse_repeat_number
+                    )?;
+                }
+                WindowOutput::BigWig(tracks) => {
+                    let Strands { forward, reverse } = &mut tracks[r];
+                    forward.add(id, start, end, forward_repeat_number as f32)?;
+                    reverse.add(id, start, end, reverse_repeat_number as f32)?;
+                }
+            }
         }
     }
 
@@ -158,8 +1265,13 @@ fn write_window_counts<T: std::io::Write>(
 #[cfg(test)]
 mod tests {
     use std::io::{LineWriter, Read};
+    use std::path::Path;
 
-    use super::write_window_counts;
+    use super::{
+        bases_in_windows, count_in_windows, count_windows, n_runs, window_file_names, windows,
+        write_counts, Coverage, Strands, WindowCounter, WindowOptions, WindowOutput, BLOCK_SIZE,
+    };
+    use crate::utils::{Approximate, MotifScanner};
 
     // a wrapper for making a bio::io::fasta record
     fn make_record(id: &str, seq: &[u8]) -> bio::io::fasta::Record {
@@ -167,12 +1279,29 @@ mod tests {
     }
 
     // take a record, write to a vector (fake file), then read out of this the output.
-    fn calc_windows(rec: bio::io::fasta::Record, repeat: &str, ws: usize) -> String {
+    fn calc_windows(
+        rec: bio::io::fasta::Record,
+        repeats: &[&str],
+        ws: usize,
+        approximate: Option<Approximate>,
+    ) -> String {
         let file = Vec::new();
-        let mut lw = LineWriter::new(file);
+        let mut output = WindowOutput::Tsv(LineWriter::new(file));
         let id = rec.id().to_owned();
 
-        write_window_counts(rec, &mut lw, repeat, ws, id, "tsv").unwrap();
+        let repeats: Vec<String> = repeats.iter().map(|e| e.to_string()).collect();
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&repeats).unwrap(),
+            telomeric_repeats: repeats,
+            window_size: ws,
+            step: ws,
+            approximate,
+        };
+        let counts = count_windows(rec.seq(), &options, BLOCK_SIZE);
+        write_counts(&counts, &mut output, &options, &id, 0).unwrap();
+        let WindowOutput::Tsv(lw) = output else {
+            unreachable!()
+        };
 
         // read file contents to new vec
         let mut out = Vec::new();
@@ -191,15 +1320,334 @@ mod tests {
             b"TTAGGTTAGGTTAGGCAGCATCACACTGATCATCTGATTAGGTTAGGTTAGG",
         );
 
-        let windows_calculation = calc_windows(rec, "TTAGG", 20);
+        let windows_calculation = calc_windows(rec, &["TTAGG"], 20, None);
 
         let rows: Vec<&str> = windows_calculation.lines().collect();
 
         // three in first window
-        assert_eq!(rows[0], "test1\t20\t3\t0\tTTAGG");
-        // none in second
-        assert_eq!(rows[1], "test1\t40\t0\t0\tTTAGG");
+        assert_eq!(rows[0], "test1\t20\t3\t0\tTTAGG\t0\t20\t15\t0.7500\t0.0000");
+        // none in second, nor their bases from the match over its end
+        assert_eq!(rows[1], "test1\t40\t0\t0\tTTAGG\t20\t20\t0\t0.0000\t0.0000");
         // two in third
-        assert_eq!(rows[2], "test1\t52\t2\t0\tTTAGG");
+        assert_eq!(
+            rows[2],
+            "test1\t52\t2\t0\tTTAGG\t40\t12\t10\t0.8333\t0.0000"
+        );
+    }
+
+    #[test]
+    fn test_search_multiple() {
+        let rec = make_record(
+            "test1",
+            b"TTAGGTTAGGTTAGGCAGCATCACACTGATCATCTGATGCATTAGGGTTAGGGCCCTAA",
+        );
+
+        let windows_calculation = calc_windows(rec, &["TTAGG", "TTAGGG"], 20, None);
+
+        let rows: Vec<&str> = windows_calculation.lines().collect();
+
+        // rows for the first string, then the second, in each window
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[0], "test1\t20\t3\t0\tTTAGG\t0\t20\t15\t0.7500\t0.0000");
+        assert_eq!(rows[1], "test1\t20\t0\t0\tTTAGGG\t0\t20\t0\t0.0000\t0.0000");
+        assert_eq!(
+            rows[4],
+            "test1\t59\t2\t1\tTTAGG\t40\t19\t15\t0.7895\t0.0000"
+        );
+        assert_eq!(
+            rows[5],
+            "test1\t59\t2\t1\tTTAGGG\t40\t19\t18\t0.9474\t0.0000"
+        );
+    }
+
+    #[test]
+    fn test_search_approximate() {
+        // the second copy has a substitution, the fourth a deletion
+        let rec = make_record("test1", b"TTAGGTTCGGTTAGGTTGGTTAGGCAGCATCACACTGAT");
+
+        let windows_calculation = calc_windows(
+            rec.clone(),
+            &["TTAGG"],
+            20,
+            Some(Approximate::Mismatches(1)),
+        );
+        let rows: Vec<&str> = windows_calculation.lines().collect();
+        assert_eq!(
+            rows[0],
+            "test1\t20\t2\t0\tTTAGG\t3\t0\t0\t20\t10\t0.5000\t0.0000"
+        );
+
+        let windows_calculation =
+            calc_windows(rec, &["TTAGG"], 20, Some(Approximate::EditDistance(1)));
+        let rows: Vec<&str> = windows_calculation.lines().collect();
+        assert_eq!(
+            rows[0],
+            "test1\t20\t2\t0\tTTAGG\t4\t0\t0\t20\t10\t0.5000\t0.0000"
+        );
+    }
+
+    #[test]
+    fn test_search_step() {
+        let rec = make_record(
+            "test1",
+            b"TTAGGTTAGGTTAGGCAGCATCACACTGATCATCTGATTAGGTTAGGTTAGG",
+        );
+        let repeats = vec!["TTAGG".to_string()];
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&repeats).unwrap(),
+            telomeric_repeats: repeats,
+            window_size: 20,
+            step: 10,
+            approximate: None,
+        };
+        let mut output = WindowOutput::Tsv(LineWriter::new(Vec::new()));
+        let counts = count_windows(rec.seq(), &options, BLOCK_SIZE);
+        write_counts(&counts, &mut output, &options, "test1", 0).unwrap();
+        let WindowOutput::Tsv(lw) = output else {
+            unreachable!()
+        };
+
+        let out = String::from_utf8(lw.into_inner().unwrap()).unwrap();
+        // the window start and end, and the forward count
+        let rows: Vec<(&str, &str, &str)> = out
+            .lines()
+            .map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                (columns[5], columns[1], columns[2])
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("0", "20", "3"),
+                ("10", "30", "1"),
+                ("20", "40", "0"),
+                ("30", "50", "2"),
+                ("40", "52", "2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_blocks() {
+        // a pseudo-random sequence, with some arrays and gaps
+        let mut state: u32 = 1;
+        let mut sequence: Vec<u8> = (0..2000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect();
+        sequence.splice(100..100, b"TTAGG".repeat(30));
+        sequence.splice(900..900, b"ccctaa".repeat(20));
+        sequence.splice(1500..1500, b"N".repeat(40));
+
+        let repeats = vec!["TTAGG".to_string(), "TTAGGG".to_string()];
+        for approximate in [
+            None,
+            Some(Approximate::Mismatches(1)),
+            Some(Approximate::EditDistance(2)),
+        ] {
+            // including windows with gaps between them
+            for (window_size, step) in [(50, 30), (40, 70)] {
+                let options = WindowOptions {
+                    scanner: MotifScanner::new(&repeats).unwrap(),
+                    telomeric_repeats: repeats.clone(),
+                    window_size,
+                    step,
+                    approximate,
+                };
+                let whole = count_windows(&sequence, &options, usize::MAX);
+                // however the windows are split into blocks, and however
+                // much of the sequence is read at a time
+                for block_size in [1, 100, 170] {
+                    assert_eq!(count_windows(&sequence, &options, block_size), whole);
+                    for piece in [1, 7, 500] {
+                        let mut counter = WindowCounter::new(&options, block_size);
+                        for part in sequence.chunks(piece) {
+                            counter.push(part);
+                        }
+                        assert_eq!(counter.finish(), whole);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_take() {
+        let mut sequence = b"ACGTTGCA".repeat(200);
+        sequence.splice(100..100, b"TTAGG".repeat(30));
+        sequence.splice(1200..1200, b"CCTAA".repeat(30));
+
+        let repeats = vec!["TTAGG".to_string()];
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&repeats).unwrap(),
+            telomeric_repeats: repeats,
+            window_size: 50,
+            step: 50,
+            approximate: Some(Approximate::EditDistance(1)),
+        };
+        let whole = count_windows(&sequence, &options, 10);
+        let write = |counts| {
+            let mut output = WindowOutput::Tsv(LineWriter::new(Vec::new()));
+            write_counts(&counts, &mut output, &options, "test1", 0).unwrap();
+            let WindowOutput::Tsv(lw) = output else {
+                unreachable!()
+            };
+            String::from_utf8(lw.into_inner().unwrap()).unwrap()
+        };
+
+        // the windows are written as the chunks are counted, before the
+        // whole sequence is added
+        let mut counter = WindowCounter::new(&options, 10);
+        let mut out = String::new();
+        for part in sequence.chunks(300) {
+            counter.push(part);
+            out += &write(counter.take());
+            if out.is_empty() {
+                continue;
+            }
+            assert!(out.lines().count() < whole.windows.len());
+        }
+        assert!(!out.is_empty());
+        out += &write(counter.finish());
+        assert_eq!(out, write(whole));
+    }
+
+    #[test]
+    fn test_windows() {
+        assert_eq!(windows(25, 10, 10), vec![(0, 10), (10, 20), (20, 25)]);
+        assert_eq!(
+            windows(25, 10, 5),
+            vec![(0, 10), (5, 15), (10, 20), (15, 25)]
+        );
+        assert_eq!(windows(5, 10, 5), vec![(0, 5)]);
+        assert!(windows(0, 10, 5).is_empty());
+    }
+
+    #[test]
+    fn test_search_n_content() {
+        // a gap in the middle of the second window
+        let rec = make_record("test1", b"TTAGGTTAGGTTAGGCAGCANNNNNNNNNNTGATCATCTG");
+
+        let windows_calculation = calc_windows(rec, &["TTAGG"], 20, None);
+        let rows: Vec<&str> = windows_calculation.lines().collect();
+        assert_eq!(rows[1], "test1\t40\t0\t0\tTTAGG\t20\t20\t0\t0.0000\t0.5000");
+    }
+
+    #[test]
+    fn test_bases_in_windows() {
+        // a match overlapping the end of a window is in neither its
+        // count nor its bases
+        let matches = vec![(0, 6), (3, 9), (8, 14), (18, 24)];
+        let windows = vec![(0, 10), (5, 15), (10, 20), (20, 30)];
+        assert_eq!(count_in_windows(&matches, &windows), vec![2, 1, 0, 0]);
+        assert_eq!(bases_in_windows(&matches, &windows), vec![9, 6, 0, 0]);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let coverage = Coverage::new(vec![(10, 15), (0, 5), (12, 20), (30, 35)]);
+        assert_eq!(coverage.intervals, vec![(0, 5), (10, 20), (30, 35)]);
+        assert_eq!(coverage.covered(0, 40), 20);
+        assert_eq!(coverage.covered(3, 12), 4);
+        assert_eq!(coverage.covered(20, 30), 0);
+        assert_eq!(coverage.covered(34, 40), 1);
+
+        assert_eq!(n_runs(b"NNACGnnNTN"), vec![(0, 2), (5, 8), (9, 10)]);
+    }
+
+    #[test]
+    fn test_search_offset() {
+        // a region starting 1000 bases into the chromosome
+        let rec = make_record("test1", b"TTAGGTTAGGTTAGGCAGCATCACACTGATCAT");
+        let repeats = vec!["TTAGG".to_string()];
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&repeats).unwrap(),
+            telomeric_repeats: repeats,
+            window_size: 20,
+            step: 20,
+            approximate: None,
+        };
+        let mut output = WindowOutput::BedGraph(vec![Strands {
+            forward: LineWriter::new(Vec::new()),
+            reverse: LineWriter::new(Vec::new()),
+        }]);
+        let counts = count_windows(rec.seq(), &options, BLOCK_SIZE);
+        write_counts(&counts, &mut output, &options, "test1", 1000).unwrap();
+        let WindowOutput::BedGraph(mut tracks) = output else {
+            unreachable!()
+        };
+        let Strands { forward, reverse } = tracks.pop().unwrap();
+
+        // a track for each strand
+        let out = String::from_utf8(forward.into_inner().unwrap()).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows, vec!["test1\t1000\t1020\t3", "test1\t1020\t1033\t0"]);
+        let out = String::from_utf8(reverse.into_inner().unwrap()).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows, vec!["test1\t1000\t1020\t0", "test1\t1020\t1033\t0"]);
+    }
+    #[test]
+    fn test_search_bedgraph_multiple() {
+        let rec = make_record(
+            "test1",
+            b"TTAGGTTAGGTTAGGCAGCATCACACTGATCATCTGATGCATTAGGGTTAGGGCCCTAA",
+        );
+        let repeats = vec!["TTAGG".to_string(), "TTAGGG".to_string()];
+        let options = WindowOptions {
+            scanner: MotifScanner::new(&repeats).unwrap(),
+            telomeric_repeats: repeats,
+            window_size: 20,
+            step: 20,
+            approximate: None,
+        };
+        let tracks = (0..2)
+            .map(|_| Strands {
+                forward: LineWriter::new(Vec::new()),
+                reverse: LineWriter::new(Vec::new()),
+            })
+            .collect();
+        let mut output = WindowOutput::BedGraph(tracks);
+        let counts = count_windows(rec.seq(), &options, BLOCK_SIZE);
+        write_counts(&counts, &mut output, &options, "test1", 0).unwrap();
+        let WindowOutput::BedGraph(tracks) = output else {
+            unreachable!()
+        };
+
+        // a track for each strand of each string
+        let rows: Vec<Vec<String>> = tracks
+            .into_iter()
+            .flat_map(|strands| [strands.forward, strands.reverse])
+            .map(|track| {
+                let out = String::from_utf8(track.into_inner().unwrap()).unwrap();
+                out.lines().map(|row| row.to_string()).collect()
+            })
+            .collect();
+        let expected = [
+            ["test1\t0\t20\t3", "test1\t20\t40\t0", "test1\t40\t59\t2"],
+            ["test1\t0\t20\t0", "test1\t20\t40\t0", "test1\t40\t59\t1"],
+            ["test1\t0\t20\t0", "test1\t20\t40\t0", "test1\t40\t59\t2"],
+            ["test1\t0\t20\t0", "test1\t20\t40\t0", "test1\t40\t59\t1"],
+        ];
+        assert_eq!(rows, expected);
+    }
+    #[test]
+    fn test_window_file_names() {
+        let repeats = vec!["TTAGG".to_string(), "TTAGGG".to_string()];
+        let names =
+            |extension| window_file_names(Path::new("o"), Path::new("x"), extension, &repeats);
+        assert_eq!(names("tsv"), vec!["o/x_telomeric_repeat_windows.tsv"]);
+        // a file for each strand of each string, as BigWig has a single track
+        assert_eq!(
+            names("bigwig"),
+            vec![
+                "o/x_telomeric_repeat_windows_TTAGG_forward.bw",
+                "o/x_telomeric_repeat_windows_TTAGG_reverse.bw",
+                "o/x_telomeric_repeat_windows_TTAGGG_forward.bw",
+                "o/x_telomeric_repeat_windows_TTAGGG_reverse.bw",
+            ]
+        );
     }
 }
@@ -0,0 +1,387 @@
+use crate::call::{self, Strand, TelomericArray};
+use crate::search::{ArrayMerger, WindowCounter, WindowOptions, BLOCK_SIZE, CHUNK_SIZE};
+use crate::{reader, utils, SubCommand};
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, File};
+use std::io::LineWriter;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The entry point for `tidk summary`.
+///
+/// For each record, check whether there are telomeric arrays at
+/// either end, and classify the record as telomere-to-telomere,
+/// having a telomere at one end, or none. The arrays are merged from
+/// the motif positions of the window counting in `search` and `find`,
+/// as each record is read, so whole chromosomes are never held in
+/// memory.
+pub fn summary(matches: &clap::ArgMatches, sc: SubCommand) -> Result<()> {
+    let input_fasta = matches
+        .get_one::<PathBuf>("fasta")
+        .expect("errored by clap");
+    let reference = reader::get_reference(matches);
+    let mut records = reader::sequence_reader(input_fasta, reference.as_deref(), None)?;
+
+    let telomeric_repeats = call::get_telomeric_repeats(matches)?;
+    eprintln!(
+        "[+]\tSummarising telomeres for repeat(s): {}",
+        telomeric_repeats.join(", ")
+    );
+
+    let max_gap = *matches
+        .get_one::<usize>("max-gap")
+        .expect("defaulted by clap");
+    let min_copies = *matches
+        .get_one::<usize>("min-copies")
+        .expect("defaulted by clap");
+    let max_distance = *matches
+        .get_one::<usize>("max-distance")
+        .expect("defaulted by clap");
+    let outdir = matches.get_one::<PathBuf>("dir").expect("errored by clap");
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .expect("errored by clap");
+
+    // create directory for output
+    create_dir_all(outdir)?;
+
+    // only the motif positions are needed, so the windows are as large
+    // as the blocks counted
+    let options = WindowOptions {
+        scanner: utils::MotifScanner::new(&telomeric_repeats)?,
+        telomeric_repeats,
+        window_size: BLOCK_SIZE,
+        step: BLOCK_SIZE,
+        approximate: None,
+    };
+
+    let mut summaries = Vec::new();
+    // iterate over the fasta records, a chunk at a time
+    while let Some(header) = records.next_sequence()? {
+        let mut counter = WindowCounter::new(&options, BLOCK_SIZE);
+        let mut merger = ArrayMerger::new(max_gap, min_copies);
+        // the arrays which might be near either end, of the first
+        // `length` bases
+        let mut arrays = Vec::new();
+        let mut keep = |merged: Vec<(TelomericArray, usize)>, length: usize| {
+            arrays.extend(
+                merged
+                    .into_iter()
+                    .map(|(array, i)| (options.telomeric_repeats[i].as_str(), array)),
+            );
+            // an array too far from the start, and from the bases read so
+            // far, is too far from either end
+            arrays.retain(|(_, array): &(&str, TelomericArray)| {
+                array.start <= max_distance || array.end + max_distance >= length
+            });
+        };
+
+        let mut sequence = Vec::new();
+        let mut length = 0;
+        loop {
+            sequence.clear();
+            let read = records.read(&mut sequence, CHUNK_SIZE)?;
+            length += read;
+            counter.push(&sequence);
+            if read < CHUNK_SIZE {
+                break;
+            }
+            keep(
+                merger.merge(&counter.take(), &options.telomeric_repeats, false),
+                length,
+            );
+        }
+        keep(
+            merger.merge(&counter.finish(), &options.telomeric_repeats, true),
+            length,
+        );
+
+        let summary = summarise_record(&header.id, length, &arrays, max_distance);
+        eprintln!(
+            "[+]\tChromosome {} processed: {}",
+            summary.id, summary.status
+        );
+        summaries.push(summary);
+    }
+
+    // write the TSV
+    let tsv_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomere_summary.tsv"
+    );
+    let tsv_file = File::create(&tsv_name)?;
+    let mut tsv_file = LineWriter::new(tsv_file);
+    write_tsv(&summaries, &mut tsv_file)?;
+
+    // and the JSON
+    let json_name = format!(
+        "{}/{}{}",
+        outdir.display(),
+        output.display(),
+        "_telomere_summary.json"
+    );
+    let json_file = File::create(&json_name)?;
+    serde_json::to_writer_pretty(json_file, &summaries)?;
+
+    let t2t = summaries
+        .iter()
+        .filter(|s| s.status == Status::TelomereToTelomere)
+        .count();
+    eprintln!(
+        "[+]\tFinished summarising genome: {} of {} records are telomere-to-telomere.",
+        t2t,
+        summaries.len()
+    );
+    eprintln!("[+]\tWritten to: {} and {}", tsv_name, json_name);
+
+    // optional log file
+    sc.log(matches)?;
+
+    Ok(())
+}
+
+/// Whether a record has telomeres at both ends, one end, or neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Status {
+    #[serde(rename = "T2T")]
+    TelomereToTelomere,
+    #[serde(rename = "one-end")]
+    OneEnd,
+    #[serde(rename = "none")]
+    NoTelomere,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::TelomereToTelomere => write!(f, "T2T"),
+            Status::OneEnd => write!(f, "one-end"),
+            Status::NoTelomere => write!(f, "none"),
+        }
+    }
+}
+
+/// The telomere found at one end of a record.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndTelomere {
+    /// The telomeric repeat making up the array.
+    pub telomeric_repeat: String,
+    /// The length of the array in base pairs.
+    pub length: usize,
+    /// The strand of the array, relative to the telomeric repeat.
+    pub strand: String,
+    /// The distance of the array from the end of the record.
+    pub distance: usize,
+    /// Whether the array is on the strand we expect at this end;
+    /// the C-rich strand at the 5' end and the G-rich strand at the 3' end.
+    pub expected_orientation: bool,
+}
+
+/// The per-record summary.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordSummary {
+    /// The record ID.
+    pub id: String,
+    /// The length of the record.
+    pub length: usize,
+    /// The telomere at the start (5' end) of the record, if any.
+    pub left: Option<EndTelomere>,
+    /// The telomere at the end (3' end) of the record, if any.
+    pub right: Option<EndTelomere>,
+    /// The overall classification.
+    pub status: Status,
+}
+
+/// A G-rich telomeric repeat (e.g. TTAGGG) sits on the forward strand
+/// at the 3' end of a chromosome, and its reverse complement at the 5'
+/// end. For a C-rich repeat (e.g. AACCCT), it's the other way round.
+fn is_g_rich(telomeric_repeat: &str) -> bool {
+    let g = telomeric_repeat.matches('G').count();
+    let c = telomeric_repeat.matches('C').count();
+    g >= c
+}
+
+/// The strand we expect an array of a telomeric repeat to be on, at the
+/// left (5') or right (3') end of a sequence. At the 3' end, we expect
+/// the G-rich strand.
+pub(crate) fn expected_strand(telomeric_repeat: &str, is_left: bool) -> Strand {
+    if is_g_rich(telomeric_repeat) != is_left {
+        Strand::Forward
+    } else {
+        Strand::Reverse
+    }
+}
+
+/// Make an [`EndTelomere`] from an array at one end of a record.
+fn end_telomere(
+    telomeric_repeat: &str,
+    array: &TelomericArray,
+    distance: usize,
+    is_left: bool,
+) -> EndTelomere {
+    let expected_strand = expected_strand(telomeric_repeat, is_left);
+
+    EndTelomere {
+        telomeric_repeat: telomeric_repeat.to_string(),
+        length: array.len(),
+        strand: array.strand.to_string(),
+        distance,
+        expected_orientation: array.strand == expected_strand,
+    }
+}
+
+/// Find the arrays closest to each end of a record, within `max_distance`
+/// of the end, preferring those in the expected orientation, and classify
+/// the record. An end only counts as a telomere if its array is the
+/// right way round, otherwise it's more likely an interstitial repeat or
+/// a misassembly.
+fn summarise_record(
+    id: &str,
+    length: usize,
+    arrays: &[(&str, TelomericArray)],
+    max_distance: usize,
+) -> RecordSummary {
+    let left = arrays
+        .iter()
+        .filter(|(_, a)| a.start <= max_distance)
+        .map(|(repeat, a)| end_telomere(repeat, a, a.start, true))
+        .min_by_key(|end| (!end.expected_orientation, end.distance));
+    let right = arrays
+        .iter()
+        .filter(|(_, a)| length - a.end <= max_distance)
+        .map(|(repeat, a)| end_telomere(repeat, a, length - a.end, false))
+        .min_by_key(|end| (!end.expected_orientation, end.distance));
+
+    let is_telomere =
+        |end: &Option<EndTelomere>| end.as_ref().is_some_and(|end| end.expected_orientation);
+    let status = match (is_telomere(&left), is_telomere(&right)) {
+        (true, true) => Status::TelomereToTelomere,
+        (false, false) => Status::NoTelomere,
+        _ => Status::OneEnd,
+    };
+
+    RecordSummary {
+        id: id.to_string(),
+        length,
+        left,
+        right,
+        status,
+    }
+}
+
+/// Write the summaries as a TSV. Missing values are written as `NA`.
+fn write_tsv<T: std::io::Write>(
+    summaries: &[RecordSummary],
+    file: &mut LineWriter<T>,
+) -> Result<()> {
+    writeln!(
+        file,
+        "id\tlength\tleft_telomere_length\tleft_strand\tleft_distance\tleft_expected_orientation\tright_telomere_length\tright_strand\tright_distance\tright_expected_orientation\tstatus"
+    )?;
+
+    let format_end = |end: &Option<EndTelomere>| match end {
+        Some(e) => format!(
+            "{}\t{}\t{}\t{}",
+            e.length, e.strand, e.distance, e.expected_orientation
+        ),
+        None => "0\tNA\tNA\tNA".to_string(),
+    };
+
+    for summary in summaries {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}",
+            summary.id,
+            summary.length,
+            format_end(&summary.left),
+            format_end(&summary.right),
+            summary.status
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summarise(sequence: &str) -> RecordSummary {
+        let arrays: Vec<(&str, TelomericArray)> = call::call_arrays(sequence, "TTAGG", 0, 3)
+            .into_iter()
+            .map(|a| ("TTAGG", a))
+            .collect();
+        summarise_record("test", sequence.len(), &arrays, 5)
+    }
+
+    #[test]
+    fn test_summary_t2t() {
+        let summary = summarise("CCTAACCTAACCTAAGCATGCATGCATTTAGGTTAGGTTAGGTTAGGA");
+
+        assert_eq!(summary.status, Status::TelomereToTelomere);
+        let left = summary.left.unwrap();
+        assert_eq!((left.length, left.distance), (15, 0));
+        assert!(left.expected_orientation);
+        let right = summary.right.unwrap();
+        assert_eq!((right.length, right.distance), (20, 1));
+        assert!(right.expected_orientation);
+    }
+
+    #[test]
+    fn test_summary_one_end() {
+        let summary = summarise("CCTAACCTAACCTAAGCATGCATGCATGCATGCATGCAT");
+
+        assert_eq!(summary.status, Status::OneEnd);
+        assert!(summary.left.unwrap().expected_orientation);
+        assert!(summary.right.is_none());
+
+        // forward strand at the 5' end is the wrong way round
+        let summary = summarise("TTAGGTTAGGTTAGGGCATGCATGCATGCATGCATCCTAACCTAACCTAA");
+
+        assert_eq!(summary.status, Status::NoTelomere);
+        assert!(!summary.left.unwrap().expected_orientation);
+        assert!(!summary.right.unwrap().expected_orientation);
+    }
+
+    #[test]
+    fn test_summary_swapped_strands() {
+        // the arrays of a T2T sequence, each at the wrong end
+        let summary = summarise("TTAGGTTAGGTTAGGTTAGGAGCATGCATGCATCCTAACCTAACCTAA");
+
+        assert_eq!(summary.status, Status::NoTelomere);
+        let left = summary.left.unwrap();
+        assert_eq!(
+            (left.strand.as_str(), left.expected_orientation),
+            ("+", false)
+        );
+        let right = summary.right.unwrap();
+        assert_eq!(
+            (right.strand.as_str(), right.expected_orientation),
+            ("-", false)
+        );
+
+        // but an array in the expected orientation counts, even if
+        // there's one the wrong way round nearer the end
+        let sequence = "TTAGGTTAGGTTAGGCCTAACCTAACCTAAGCATGCATGCATTTAGGTTAGGTTAGG";
+        let arrays: Vec<(&str, TelomericArray)> = call::call_arrays(sequence, "TTAGG", 0, 3)
+            .into_iter()
+            .map(|a| ("TTAGG", a))
+            .collect();
+        let summary = summarise_record("test", sequence.len(), &arrays, 20);
+
+        assert_eq!(summary.status, Status::TelomereToTelomere);
+        let left = summary.left.unwrap();
+        assert_eq!((left.distance, left.expected_orientation), (15, true));
+    }
+
+    #[test]
+    fn test_summary_none() {
+        let summary = summarise("GCATGCATGCATTTAGGTTAGGTTAGGGCATGCATGCATGCAT");
+
+        assert_eq!(summary.status, Status::NoTelomere);
+    }
+}
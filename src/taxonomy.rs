@@ -0,0 +1,382 @@
+use crate::clades::CladeTable;
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The telomeric repeats used when no clade in the lineage has a known
+/// repeat: the ancestral metazoan TTAGGG, and the ancestral arthropod
+/// TTAGG, written as in the clade table.
+pub const FALLBACK_REPEATS: [&str; 2] = ["AACCCT", "AACCT"];
+
+/// The taxid of the root of the NCBI taxonomy.
+const ROOT: u32 = 1;
+
+/// A taxon to look up in the NCBI taxonomy.
+#[derive(Debug, Clone)]
+pub enum Query {
+    /// An NCBI taxid.
+    Taxid(u32),
+    /// A species name, or the name of any other taxon.
+    Species(String),
+}
+
+/// A taxon in the NCBI taxonomy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Taxon {
+    pub taxid: u32,
+    /// The scientific name.
+    pub name: String,
+    /// The rank, e.g. order, which may be `no rank` or `clade`.
+    pub rank: String,
+}
+
+impl Display for Taxon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} (taxid {})", self.rank, self.name, self.taxid)
+    }
+}
+
+/// The telomeric repeats of a taxon, from the nearest clade in its
+/// lineage with known telomeric repeats.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    /// The taxon asked for.
+    pub taxon: Taxon,
+    /// The taxon itself, or its nearest ancestor, which is in the
+    /// clade table, if there is one.
+    pub clade: Option<Taxon>,
+    /// The telomeric repeats of the clade, or [`FALLBACK_REPEATS`].
+    pub telomeric_repeats: Vec<String>,
+}
+
+/// The clade the telomeric repeats came from, for the log files.
+impl Display for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.clade {
+            Some(clade) => write!(f, "{}, for the {}", clade, self.taxon),
+            None => write!(f, "None, for the {}, so the ancestral repeats", self.taxon),
+        }
+    }
+}
+
+impl Resolved {
+    /// Tell the user which clade was used, and why.
+    pub fn report(&self) {
+        match &self.clade {
+            Some(clade) if *clade == self.taxon => {
+                eprintln!("[+]\tFound the {} in the clade table", clade)
+            }
+            Some(clade) => eprintln!(
+                "[+]\tThe nearest clade to the {} in the clade table is the {}",
+                self.taxon, clade
+            ),
+            None => eprintln!(
+                "[-]\tWarning: nothing in the lineage of the {} is in the clade table, so falling back to the ancestral TTAGGG and TTAGG repeats",
+                self.taxon
+            ),
+        }
+    }
+}
+
+/// Get the taxon asked for with `--taxid` or `--species`, if either
+/// was given.
+pub fn get_query(matches: &clap::ArgMatches) -> Option<Query> {
+    if let Some(taxid) = matches.get_one::<u32>("taxid") {
+        Some(Query::Taxid(*taxid))
+    } else {
+        matches
+            .get_one::<String>("species")
+            .map(|species| Query::Species(species.clone()))
+    }
+}
+
+/// Find the telomeric repeats for the taxon asked for with `--taxid` or
+/// `--species`, if either was given, using the NCBI taxdump in the
+/// `--taxdump` directory.
+pub fn resolve(matches: &clap::ArgMatches, table: &CladeTable) -> Result<Option<Resolved>> {
+    let Some(query) = get_query(matches) else {
+        return Ok(None);
+    };
+    let taxdump = matches
+        .get_one::<PathBuf>("taxdump")
+        .expect("required by clap");
+    let lineage = Taxonomy::new(taxdump).lineage(&query)?;
+    Ok(Some(resolve_lineage(lineage, table)))
+}
+
+/// Walk up a lineage, from the taxon to the root, to the first taxon
+/// with telomeric repeats in the clade table.
+fn resolve_lineage(lineage: Vec<Taxon>, table: &CladeTable) -> Resolved {
+    let clade = lineage.iter().find_map(|taxon| {
+        let repeats = table.get(&taxon.name).ok()?;
+        Some((taxon.clone(), repeats.to_vec()))
+    });
+    let taxon = lineage.into_iter().next().expect("a lineage has the taxon");
+
+    match clade {
+        Some((clade, telomeric_repeats)) => Resolved {
+            taxon,
+            clade: Some(clade),
+            telomeric_repeats,
+        },
+        None => Resolved {
+            taxon,
+            clade: None,
+            telomeric_repeats: FALLBACK_REPEATS.iter().map(|e| e.to_string()).collect(),
+        },
+    }
+}
+
+/// A local copy of the NCBI taxonomy, i.e. the `names.dmp` and
+/// `nodes.dmp` (and optionally `merged.dmp`) from `taxdump.tar.gz`.
+/// These are large, so only what is needed for a lineage is kept.
+pub struct Taxonomy {
+    dir: PathBuf,
+}
+
+impl Taxonomy {
+    /// The taxonomy in a taxdump directory.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The lineage of a taxon, starting with the taxon itself, and
+    /// ending at the root.
+    pub fn lineage(&self, query: &Query) -> Result<Vec<Taxon>> {
+        let taxid = match query {
+            Query::Taxid(taxid) => self.merged(*taxid)?,
+            Query::Species(species) => self.find_name(species)?,
+        };
+
+        // the parent and rank of every node, with the ranks shared
+        let mut ranks: Vec<String> = Vec::new();
+        let mut nodes: HashMap<u32, (u32, usize)> = HashMap::new();
+        self.read_dmp("nodes.dmp", |fields| {
+            let rank = field(fields, 2)?;
+            let rank = match ranks.iter().position(|r| r == rank) {
+                Some(rank) => rank,
+                None => {
+                    ranks.push(rank.to_string());
+                    ranks.len() - 1
+                }
+            };
+            nodes.insert(taxid_field(fields, 0)?, (taxid_field(fields, 1)?, rank));
+            Ok(())
+        })?;
+
+        let mut taxids = vec![taxid];
+        let mut current = taxid;
+        while current != ROOT {
+            let (parent, _) = nodes
+                .get(&current)
+                .with_context(|| format!("Taxid {} is not in nodes.dmp.", current))?;
+            if taxids.contains(parent) {
+                bail!("The lineage of taxid {} has a loop in nodes.dmp.", taxid);
+            }
+            taxids.push(*parent);
+            current = *parent;
+        }
+
+        // then the names of just the taxa in the lineage
+        let wanted: HashSet<u32> = taxids.iter().copied().collect();
+        let mut names: HashMap<u32, String> = HashMap::new();
+        self.read_dmp("names.dmp", |fields| {
+            let taxid = taxid_field(fields, 0)?;
+            if wanted.contains(&taxid) && field(fields, 3)? == "scientific name" {
+                names.insert(taxid, field(fields, 1)?.to_string());
+            }
+            Ok(())
+        })?;
+
+        taxids
+            .into_iter()
+            .map(|taxid| {
+                Ok(Taxon {
+                    taxid,
+                    name: names
+                        .remove(&taxid)
+                        .with_context(|| format!("Taxid {} has no name in names.dmp.", taxid))?,
+                    rank: ranks[nodes[&taxid].1].clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The taxid of a name, ignoring case. Scientific names are preferred,
+    /// but any other name (e.g. a synonym, or a common name) is used if
+    /// it only belongs to one taxon.
+    fn find_name(&self, name: &str) -> Result<u32> {
+        let mut scientific = HashSet::new();
+        let mut other = HashSet::new();
+        self.read_dmp("names.dmp", |fields| {
+            if field(fields, 1)?.eq_ignore_ascii_case(name) {
+                let taxid = taxid_field(fields, 0)?;
+                match field(fields, 3)? {
+                    "scientific name" => scientific.insert(taxid),
+                    _ => other.insert(taxid),
+                };
+            }
+            Ok(())
+        })?;
+
+        let taxids = if scientific.is_empty() {
+            other
+        } else {
+            scientific
+        };
+        let mut taxids: Vec<u32> = taxids.into_iter().collect();
+        taxids.sort_unstable();
+        match taxids[..] {
+            [] => bail!("{} is not in names.dmp.", name),
+            [taxid] => Ok(taxid),
+            _ => bail!(
+                "{} is the name of more than one taxon (taxids {:?}), so use --taxid instead.",
+                name,
+                taxids
+            ),
+        }
+    }
+
+    /// The current taxid of a taxid which may have been merged into
+    /// another, if there's a `merged.dmp`.
+    fn merged(&self, taxid: u32) -> Result<u32> {
+        if !self.dir.join("merged.dmp").exists() {
+            return Ok(taxid);
+        }
+        let mut merged = taxid;
+        self.read_dmp("merged.dmp", |fields| {
+            if taxid_field(fields, 0)? == taxid {
+                merged = taxid_field(fields, 1)?;
+            }
+            Ok(())
+        })?;
+        Ok(merged)
+    }
+
+    /// Call `row` with the fields of each row of a `.dmp` file, which
+    /// are separated by `\t|\t`, with a trailing `\t|`.
+    fn read_dmp<F: FnMut(&[&str]) -> Result<()>>(&self, name: &str, mut row: F) -> Result<()> {
+        let path = self.dir.join(name);
+        let file = File::open(&path)
+            .with_context(|| format!("Could not open {} in the taxdump", path.display()))?;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.strip_suffix("\t|").unwrap_or(&line);
+            let fields: Vec<&str> = line.split("\t|\t").collect();
+            row(&fields).with_context(|| format!("On line {} of {}", i + 1, path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// A field of a `.dmp` row.
+fn field<'a>(fields: &[&'a str], index: usize) -> Result<&'a str> {
+    fields
+        .get(index)
+        .copied()
+        .with_context(|| format!("There is no field {}.", index + 1))
+}
+
+/// A taxid field of a `.dmp` row.
+fn taxid_field(fields: &[&str], index: usize) -> Result<u32> {
+    let taxid = field(fields, index)?;
+    taxid
+        .parse()
+        .with_context(|| format!("{} is not a taxid.", taxid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a taxdump in a directory for each test, which is removed when dropped
+    struct Taxdump(Taxonomy);
+
+    impl Drop for Taxdump {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    // a tiny taxdump, with a moth, a species with no known repeat,
+    // and a renamed taxid
+    fn taxdump(test: &str) -> Taxdump {
+        let dir =
+            std::env::temp_dir().join(format!("tidk_test_taxdump_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("nodes.dmp"),
+            "1\t|\t1\t|\tno rank\t|\t\t|\n\
+             6656\t|\t1\t|\tphylum\t|\t\t|\n\
+             7088\t|\t6656\t|\torder\t|\t\t|\n\
+             7042\t|\t7088\t|\tfamily\t|\t\t|\n\
+             171605\t|\t7042\t|\tspecies\t|\t\t|\n\
+             9999\t|\t6656\t|\tspecies\t|\t\t|\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("names.dmp"),
+            "1\t|\troot\t|\t\t|\tscientific name\t|\n\
+             6656\t|\tArthropoda\t|\t\t|\tscientific name\t|\n\
+             7088\t|\tLepidoptera\t|\t\t|\tscientific name\t|\n\
+             7042\t|\tNymphalidae\t|\t\t|\tscientific name\t|\n\
+             171605\t|\tVanessa cardui\t|\t\t|\tscientific name\t|\n\
+             171605\t|\tpainted lady\t|\t\t|\tgenbank common name\t|\n\
+             9999\t|\tArthropodus novus\t|\t\t|\tscientific name\t|\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("merged.dmp"), "12345\t|\t171605\t|\n").unwrap();
+        Taxdump(Taxonomy::new(dir))
+    }
+
+    #[test]
+    fn test_lineage() {
+        let taxdump = taxdump("lineage");
+        let taxonomy = &taxdump.0;
+        let lineage = taxonomy.lineage(&Query::Taxid(171605)).unwrap();
+        let names: Vec<&str> = lineage.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Vanessa cardui",
+                "Nymphalidae",
+                "Lepidoptera",
+                "Arthropoda",
+                "root"
+            ]
+        );
+        assert_eq!(lineage[2].rank, "order");
+
+        for query in [
+            Query::Species("vanessa Cardui".into()),
+            Query::Species("painted lady".into()),
+            Query::Taxid(12345),
+        ] {
+            assert_eq!(taxonomy.lineage(&query).unwrap(), lineage);
+        }
+        assert!(taxonomy.lineage(&Query::Species("Vanessa".into())).is_err());
+        assert!(taxonomy.lineage(&Query::Taxid(42)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_lineage() {
+        let taxdump = taxdump("resolve");
+        let taxonomy = &taxdump.0;
+        let table = CladeTable::built_in();
+
+        let lineage = taxonomy.lineage(&Query::Taxid(171605)).unwrap();
+        let resolved = resolve_lineage(lineage, &table);
+        assert_eq!(resolved.clade.unwrap().name, "Lepidoptera");
+        assert_eq!(resolved.telomeric_repeats, ["AACCT"]);
+
+        let lineage = taxonomy.lineage(&Query::Taxid(9999)).unwrap();
+        let resolved = resolve_lineage(lineage, &table);
+        assert!(resolved.clade.is_none());
+        assert_eq!(resolved.telomeric_repeats, FALLBACK_REPEATS);
+    }
+}
@@ -1,8 +1,11 @@
+use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::{bail, Result};
 use bio::pattern_matching::bom::BOM;
 // I'd like to use shift_and, but may have to wait until next public release
 // of rust-bio (or hard code it here...)
 // see https://github.com/rust-bio/rust-bio/blob/master/src/pattern_matching/shift_and.rs
 use bio::pattern_matching::kmp::KMP;
+use bio::pattern_matching::myers::{long, Myers, MyersBuilder};
 use lexical_sort::{natural_lexical_cmp, StringSort};
 use std::cmp::min;
 
@@ -15,16 +18,114 @@ pub struct Motifs {
     pub length: usize,
 }
 
+/// The IUPAC nucleotide codes, and the bases each one stands for.
+const IUPAC_CODES: &[(u8, &[u8])] = &[
+    (b'A', b"A"),
+    (b'C', b"C"),
+    (b'G', b"G"),
+    (b'T', b"T"),
+    (b'R', b"AG"),
+    (b'Y', b"CT"),
+    (b'S', b"CG"),
+    (b'W', b"AT"),
+    (b'K', b"GT"),
+    (b'M', b"AC"),
+    (b'B', b"CGT"),
+    (b'D', b"AGT"),
+    (b'H', b"ACT"),
+    (b'V', b"ACG"),
+    (b'N', b"ACGT"),
+];
+
+/// Check a user supplied DNA string only contains IUPAC
+/// nucleotide codes, and return it uppercased.
+pub fn validate_dna_string(dna: &str) -> Result<String> {
+    if dna.is_empty() {
+        bail!("The DNA string is empty.");
+    }
+    let dna = dna.to_uppercase();
+    let invalid: Vec<char> = dna
+        .chars()
+        .filter(|c| !IUPAC_CODES.iter().any(|(code, _)| *code as char == *c))
+        .collect();
+    if !invalid.is_empty() {
+        bail!(
+            "Invalid character(s) {:?} in DNA string {}: only the IUPAC nucleotide codes ACGTRYSWKMBDHVN are allowed.",
+            invalid,
+            dna
+        );
+    }
+    Ok(dna)
+}
+
+/// Whether a motif contains any degenerate IUPAC codes,
+/// i.e. anything other than A, C, G or T.
+pub fn is_degenerate(motif: &str) -> bool {
+    motif
+        .bytes()
+        .any(|b| !matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T'))
+}
+
+/// Whether a (possibly degenerate) base in a motif matches
+/// a base in a DNA string, in either case.
+fn iupac_match(motif_base: u8, base: u8) -> bool {
+    let base = base.to_ascii_uppercase();
+    motif_base == base
+        || IUPAC_CODES
+            .iter()
+            .find(|(code, _)| *code == motif_base)
+            .is_some_and(|(_, bases)| bases.contains(&base))
+}
+
+/// A Myers matcher builder which knows about the IUPAC codes. The
+/// codes also match lowercase bases, so the string searched doesn't
+/// need to be uppercased first.
+fn iupac_myers_builder() -> MyersBuilder {
+    let mut builder = MyersBuilder::new();
+    for (code, bases) in IUPAC_CODES {
+        let lowercase = bases.iter().chain([code]).map(u8::to_ascii_lowercase);
+        builder.ambig(*code, bases.iter().copied().chain(lowercase));
+    }
+    builder
+}
+
+/// Find all the occurrences of a degenerate motif in a DNA string,
+/// in either case.
+fn find_degenerate_motifs(motif: &[u8], string: &[u8]) -> Vec<usize> {
+    let builder = iupac_myers_builder();
+    if motif.len() <= 64 {
+        let mut matcher = builder.build_64(motif);
+        matcher
+            .find_all(string, 0)
+            .map(|(start, _, _)| start)
+            .collect()
+    } else {
+        let mut matcher = builder.build_long_64(motif);
+        matcher
+            .find_all(string, 0)
+            .map(|(start, _, _)| start)
+            .collect()
+    }
+}
+
 /// Find all the occurrences of a motif in a DNA string.
+///
+/// Short motifs are found with a [`BitScanner`], and longer ones with
+/// [`find_exact_motifs`]. Degenerate motifs are matched with a Myers
+/// matcher, allowing no errors, as the exact matchers cannot handle
+/// the IUPAC codes.
 pub fn find_motifs(motif: &str, string: &str) -> Motifs {
     let motif_length = motif.len();
 
-    let matches = if motif_length < 65 {
-        let matcher = KMP::new(motif.as_bytes());
-        matcher.find_all(string.as_bytes()).collect::<Vec<usize>>()
+    let matches = if is_degenerate(motif) {
+        find_degenerate_motifs(motif.as_bytes(), string.as_bytes())
+    } else if (1..=MAX_BIT_MOTIF_LENGTH).contains(&motif_length) {
+        BitScanner::new(&[motif])
+            .expect("a short motif of A, C, G and T")
+            .find_all(string.as_bytes())
+            .swap_remove(0)
     } else {
-        let matcher = BOM::new(motif.as_bytes());
-        matcher.find_all(string.as_bytes()).collect::<Vec<usize>>()
+        find_exact_motifs(motif.as_bytes(), string.as_bytes())
     };
 
     Motifs {
@@ -33,6 +134,397 @@ pub fn find_motifs(motif: &str, string: &str) -> Motifs {
     }
 }
 
+/// Find all the occurrences of a motif of A, C, G and T in a DNA string
+/// with KMP, or BOM for motifs longer than 64 bases. Neither is case
+/// insensitive, so as for a [`BitScanner`], both the motif and the string
+/// are uppercased first.
+pub fn find_exact_motifs(motif: &[u8], string: &[u8]) -> Vec<usize> {
+    let (motif, string) = (motif.to_ascii_uppercase(), string.to_ascii_uppercase());
+    if motif.len() < 65 {
+        KMP::new(&motif).find_all(&string).collect()
+    } else {
+        BOM::new(&motif).find_all(&string).collect()
+    }
+}
+
+/// The 2-bit code of each base, in either case, with anything else
+/// (e.g. N) as [`NOT_ACGT`].
+const BASE_CODES: [u8; 256] = {
+    let mut codes = [NOT_ACGT; 256];
+    codes[b'A' as usize] = 0;
+    codes[b'a' as usize] = 0;
+    codes[b'C' as usize] = 1;
+    codes[b'c' as usize] = 1;
+    codes[b'G' as usize] = 2;
+    codes[b'g' as usize] = 2;
+    codes[b'T' as usize] = 3;
+    codes[b't' as usize] = 3;
+    codes
+};
+/// The code of a base which isn't A, C, G or T.
+const NOT_ACGT: u8 = 4;
+
+/// The longest motif a [`BitScanner`] can find, as each base takes
+/// 2 bits of a `u64`.
+pub const MAX_BIT_MOTIF_LENGTH: usize = 32;
+
+/// Finds short motifs of A, C, G and T in a DNA string in a single pass.
+/// The last 32 bases are kept packed, 2 bits each, into an integer as
+/// the scanner slides along the string, so checking for a motif ending
+/// at each base is a single comparison. Telomeric repeats are short, so
+/// this is much faster than the general purpose matchers, and the
+/// string doesn't need to be uppercased first.
+pub struct BitScanner {
+    lengths: Vec<PackedMotifs>,
+    /// The number of motifs.
+    motif_number: usize,
+}
+
+/// The packed motifs of one length, for a [`BitScanner`].
+struct PackedMotifs {
+    length: usize,
+    /// The mask for the last `length` packed bases.
+    mask: u64,
+    /// Each packed motif, along with its index.
+    motifs: Vec<(u64, usize)>,
+}
+
+impl BitScanner {
+    /// Pack the motifs, which can only have A, C, G and T, and be at
+    /// most [`MAX_BIT_MOTIF_LENGTH`] long.
+    pub fn new<M: AsRef<[u8]>>(motifs: &[M]) -> Result<Self> {
+        let mut lengths: Vec<PackedMotifs> = Vec::new();
+        for (i, motif) in motifs.iter().enumerate() {
+            let motif = motif.as_ref();
+            if !(1..=MAX_BIT_MOTIF_LENGTH).contains(&motif.len()) {
+                bail!(
+                    "Only motifs of 1 to {} bases can be packed, not {}.",
+                    MAX_BIT_MOTIF_LENGTH,
+                    motif.len()
+                );
+            }
+            let mut packed = 0;
+            for base in motif {
+                match BASE_CODES[*base as usize] {
+                    NOT_ACGT => bail!("Only A, C, G and T can be packed, not {}.", *base as char),
+                    code => packed = (packed << 2) | code as u64,
+                }
+            }
+            match lengths.iter_mut().find(|l| l.length == motif.len()) {
+                Some(same_length) => same_length.motifs.push((packed, i)),
+                None => lengths.push(PackedMotifs {
+                    length: motif.len(),
+                    mask: bit_mask(motif.len()),
+                    motifs: vec![(packed, i)],
+                }),
+            }
+        }
+        Ok(Self {
+            lengths,
+            motif_number: motifs.len(),
+        })
+    }
+
+    /// Find all the (possibly overlapping) occurrences of each motif in a
+    /// DNA string, in either case. Returns their starts, for each motif in
+    /// the order they were given.
+    pub fn find_all(&self, string: &[u8]) -> Vec<Vec<usize>> {
+        let mut indexes = vec![Vec::new(); self.motif_number];
+        for packed_motifs in &self.lengths {
+            packed_motifs.find_all(string, &mut indexes);
+        }
+        indexes
+    }
+}
+
+impl PackedMotifs {
+    /// Find the motifs of this length, adding their starts to the
+    /// `indexes` of each motif. There's usually a motif and its reverse
+    /// complement, so one or two motifs are compared without a loop.
+    fn find_all(&self, string: &[u8], indexes: &mut [Vec<usize>]) {
+        match self.motifs[..] {
+            [(first, _)] => self.scan(string, indexes, |last| last == first),
+            [(first, _), (second, _)] => {
+                self.scan(string, indexes, |last| (last == first) | (last == second))
+            }
+            _ => self.scan(string, indexes, |last| {
+                self.motifs.iter().any(|(motif, _)| last == *motif)
+            }),
+        }
+    }
+
+    /// Slide along the string, only looking for which motif matched when
+    /// the last bases `might_match` one. The loop has no other branches,
+    /// so it's fast.
+    fn scan<F: Fn(u64) -> bool>(&self, string: &[u8], indexes: &mut [Vec<usize>], might_match: F) {
+        // the last bases, and the bases among them which aren't A, C, G
+        // or T, which to begin with is all of them
+        let mut packed: u64 = 0;
+        let mut invalid: u64 = u64::MAX;
+        for (i, base) in string.iter().enumerate() {
+            let code = BASE_CODES[*base as usize];
+            packed = (packed << 2) | (code & 3) as u64;
+            // both bits set for a base which isn't A, C, G or T
+            let not_acgt = (code >> 2) as u64 * 3;
+            invalid = (invalid << 2) | not_acgt;
+            let last = packed & self.mask;
+            if might_match(last) && invalid & self.mask == 0 {
+                for (motif, index) in &self.motifs {
+                    if last == *motif {
+                        indexes[*index].push(i + 1 - self.length);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The mask for the last `length` bases packed into a `u64`.
+fn bit_mask(length: usize) -> u64 {
+    if length >= MAX_BIT_MOTIF_LENGTH {
+        u64::MAX
+    } else {
+        (1 << (2 * length)) - 1
+    }
+}
+
+/// How approximate occurrences of a motif are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approximate {
+    /// Allow up to k substitutions (Hamming distance).
+    Mismatches(usize),
+    /// Allow up to k substitutions, insertions or deletions (edit distance).
+    EditDistance(usize),
+}
+
+/// Find the approximate occurrences of a motif in a DNA string, in either
+/// case, including exact occurrences. Returns the `(start, end)` of each occurrence.
+///
+/// Approximate matches cluster around each true occurrence, so only
+/// non-overlapping matches are kept, choosing greedily by earliest end,
+/// unless an overlapping match is a closer match.
+pub fn find_approximate_motifs(
+    motif: &str,
+    string: &[u8],
+    approximate: Approximate,
+) -> Vec<(usize, usize)> {
+    remove_overlapping_approximate(find_all_approximate_motifs(motif, string, approximate))
+}
+
+/// Find all the approximate occurrences of a motif in a DNA string, in
+/// either case, overlapping or not. Returns the `(start, end, distance)` of
+/// each occurrence, ordered by end.
+pub fn find_all_approximate_motifs(
+    motif: &str,
+    string: &[u8],
+    approximate: Approximate,
+) -> Vec<(usize, usize, usize)> {
+    let motif = motif.as_bytes();
+
+    match approximate {
+        Approximate::Mismatches(k) => {
+            if motif.len() > string.len() {
+                return vec![];
+            }
+            string
+                .windows(motif.len())
+                .enumerate()
+                .map(|(start, window)| {
+                    let distance = window
+                        .iter()
+                        .zip(motif)
+                        .filter(|(base, motif_base)| !iupac_match(**motif_base, **base))
+                        .count();
+                    (start, start + motif.len(), distance)
+                })
+                .filter(|(_, _, distance)| *distance <= k)
+                .collect()
+        }
+        Approximate::EditDistance(k) => {
+            let builder = iupac_myers_builder();
+            if motif.len() <= 64 {
+                let mut matcher: Myers<u64> = builder.build_64(motif);
+                // the distance is a u8 here, and can't be more than the
+                // motif length anyway, as the motif could be deleted
+                let k = k.min(motif.len()) as u8;
+                matcher
+                    .find_all(string, k)
+                    .map(|(start, end, distance)| (start, end, distance as usize))
+                    .collect()
+            } else {
+                let mut matcher: long::Myers<u64> = builder.build_long_64(motif);
+                matcher.find_all(string, k).collect()
+            }
+        }
+    }
+}
+
+/// Keep the non-overlapping approximate matches from [`find_all_approximate_motifs`],
+/// choosing greedily by earliest end, unless an overlapping match is a
+/// closer match.
+pub fn remove_overlapping_approximate(matches: Vec<(usize, usize, usize)>) -> Vec<(usize, usize)> {
+    let mut noverlap = NonOverlappingMatches::default();
+    for found in matches {
+        noverlap.push(found);
+    }
+    noverlap
+        .matches
+        .into_iter()
+        .map(|(start, end, _)| (start, end))
+        .collect()
+}
+
+/// Picks the non-overlapping approximate matches as they're found, as
+/// in [`remove_overlapping_approximate`], so a sequence can be searched
+/// a part at a time.
+#[derive(Debug, Default)]
+pub struct NonOverlappingMatches {
+    /// The `(start, end, distance)` of the matches picked. The last can
+    /// still be swapped for a closer match.
+    matches: Vec<(usize, usize, usize)>,
+    /// The end of the match picked before the last.
+    previous_end: usize,
+}
+
+impl NonOverlappingMatches {
+    /// Add the next match, in order of their end.
+    pub fn push(&mut self, (start, end, distance): (usize, usize, usize)) {
+        match self.matches.last_mut() {
+            Some(last) if start < last.1 => {
+                // swap for a closer match, if it doesn't overlap the one before
+                if distance < last.2 && start >= self.previous_end {
+                    *last = (start, end, distance);
+                }
+            }
+            last => {
+                if let Some(last) = last {
+                    self.previous_end = last.1;
+                }
+                self.matches.push((start, end, distance))
+            }
+        }
+    }
+
+    /// The matches picked so far.
+    pub fn matches(&self) -> &[(usize, usize, usize)] {
+        &self.matches
+    }
+
+    /// Forget the matches starting before `position`, apart from the
+    /// last, which could still be swapped.
+    pub fn forget_before(&mut self, position: usize) {
+        let before = self
+            .matches
+            .partition_point(|(start, _, _)| *start < position)
+            .min(self.matches.len().saturating_sub(1));
+        self.matches.drain(..before);
+    }
+}
+
+/// Search for several motifs, and their reverse complements, in
+/// a single pass over a DNA string. Short motifs are found with a
+/// [`BitScanner`], and any longer ones with an Aho-Corasick automaton.
+/// Matching is case insensitive, so the string does not need to be
+/// uppercased first.
+///
+/// Degenerate motifs cannot go in the automaton, so they are
+/// searched for separately with a Myers matcher.
+pub struct MotifScanner {
+    scanner: BitScanner,
+    /// For each motif in the bit scanner, the index into the output of
+    /// [`MotifScanner::find_all`]: twice the motif index, plus one for
+    /// the reverse complement.
+    scanner_indexes: Vec<usize>,
+    /// The automaton, if there are any motifs too long to pack.
+    automaton: Option<AhoCorasick>,
+    /// As `scanner_indexes`, for each pattern in the automaton.
+    pattern_indexes: Vec<usize>,
+    /// The degenerate motifs, their reverse complements, and the index
+    /// of the motif.
+    degenerate: Vec<(usize, String, String)>,
+    /// The number of motifs.
+    motif_number: usize,
+}
+
+impl MotifScanner {
+    /// Build the scanners from a list of motifs.
+    pub fn new(motifs: &[String]) -> Result<Self> {
+        let (mut short, mut scanner_indexes) = (Vec::new(), Vec::new());
+        let (mut patterns, mut pattern_indexes) = (Vec::new(), Vec::new());
+        let mut degenerate = Vec::new();
+        for (i, motif) in motifs.iter().enumerate() {
+            let motif = motif.to_uppercase();
+            let revcomp = reverse_complement(&motif);
+            if is_degenerate(&motif) {
+                degenerate.push((i, motif, revcomp));
+            } else if motif.len() <= MAX_BIT_MOTIF_LENGTH {
+                short.extend([motif, revcomp]);
+                scanner_indexes.extend([i * 2, i * 2 + 1]);
+            } else {
+                patterns.extend([motif, revcomp]);
+                pattern_indexes.extend([i * 2, i * 2 + 1]);
+            }
+        }
+
+        let automaton = if patterns.is_empty() {
+            None
+        } else {
+            Some(
+                AhoCorasick::builder()
+                    .ascii_case_insensitive(true)
+                    .match_kind(MatchKind::Standard)
+                    .build(patterns)?,
+            )
+        };
+
+        Ok(Self {
+            scanner: BitScanner::new(&short)?,
+            scanner_indexes,
+            automaton,
+            pattern_indexes,
+            degenerate,
+            motif_number: motifs.len(),
+        })
+    }
+
+    /// Find all the (possibly overlapping) occurrences of each motif in a
+    /// DNA string. Returns the forward and reverse complement [`Motifs`]
+    /// for each motif, in the order the motifs were given.
+    pub fn find_all(&self, string: &[u8]) -> Vec<(Motifs, Motifs)> {
+        let mut indexes = vec![Vec::new(); self.motif_number * 2];
+
+        for (i, starts) in self.scanner.find_all(string).into_iter().enumerate() {
+            indexes[self.scanner_indexes[i]] = starts;
+        }
+        if let Some(automaton) = &self.automaton {
+            for mat in automaton.find_overlapping_iter(string) {
+                indexes[self.pattern_indexes[mat.pattern().as_usize()]].push(mat.start());
+            }
+        }
+
+        for (i, motif, revcomp) in &self.degenerate {
+            indexes[i * 2] = find_degenerate_motifs(motif.as_bytes(), string);
+            indexes[i * 2 + 1] = find_degenerate_motifs(revcomp.as_bytes(), string);
+        }
+
+        let mut motifs = Vec::new();
+        let mut iter = indexes.into_iter();
+        while let (Some(forward), Some(reverse)) = (iter.next(), iter.next()) {
+            motifs.push((
+                Motifs {
+                    length: forward.len(),
+                    indexes: forward,
+                },
+                Motifs {
+                    length: reverse.len(),
+                    indexes: reverse,
+                },
+            ));
+        }
+        motifs
+    }
+}
+
 /// Calculate the reverse complement of a telomeric repeat.
 pub fn reverse_complement(dna: &str) -> String {
     let dna_chars = dna.chars();
@@ -45,15 +537,31 @@ pub fn reverse_complement(dna: &str) -> String {
     revcomp.into_iter().collect()
 }
 
-/// Switch complementary bases in a DNA string.
+/// Switch complementary bases in a DNA string. All the IUPAC
+/// codes are complemented, keeping their case; anything else
+/// becomes an N.
 fn switch_base(c: char) -> char {
-    match c {
+    let complement = match c.to_ascii_uppercase() {
         'A' => 'T',
         'C' => 'G',
         'T' => 'A',
         'G' => 'C',
-        'N' => 'N',
+        'R' => 'Y',
+        'Y' => 'R',
+        'S' => 'S',
+        'W' => 'W',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
         _ => 'N',
+    };
+    if c.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
     }
 }
 
@@ -180,6 +688,19 @@ mod tests {
         assert_eq!(revcomp, REVCOMP_DNA_STRING)
     }
 
+    #[test]
+    fn revcomp_iupac() {
+        let revcomp = reverse_complement("TTRGGBn");
+        assert_eq!(revcomp, "nVCCYAA")
+    }
+
+    #[test]
+    fn validate_dna_string1() {
+        assert_eq!(validate_dna_string("ttnggg").unwrap(), "TTNGGG");
+        assert!(validate_dna_string("TTAGGX").is_err());
+        assert!(validate_dna_string("").is_err());
+    }
+
     // string rotation tests
 
     // define a few strings here,
@@ -236,4 +757,135 @@ mod tests {
         let motifs = find_motifs(CANONICAL, HAYSTACK);
         assert_eq!(motifs.indexes, EXPECTED)
     }
+
+    #[test]
+    fn motifs_iupac() {
+        // W is A or T
+        let motifs = find_motifs("AWCCT", HAYSTACK);
+        assert_eq!(motifs.indexes, EXPECTED);
+        let motifs = find_motifs("AACCN", "AACCTAACCGAACCAACC");
+        assert_eq!(motifs.indexes, vec![0, 5, 10]);
+    }
+
+    #[test]
+    fn approximate_motifs1() {
+        // the deleted copy is only found when allowing indels
+        let mismatches =
+            find_approximate_motifs(CANONICAL, HAYSTACK.as_bytes(), Approximate::Mismatches(1));
+        assert_eq!(mismatches.len(), 7);
+        let edit_distance =
+            find_approximate_motifs(CANONICAL, HAYSTACK.as_bytes(), Approximate::EditDistance(1));
+        assert_eq!(edit_distance.len(), 8);
+        assert_eq!(edit_distance[6], (30, 34));
+    }
+
+    #[test]
+    fn approximate_motifs2() {
+        // a substitution in the middle copy
+        let haystack = b"AACCTAAGCTAACCT";
+        let mismatches = find_approximate_motifs(CANONICAL, haystack, Approximate::Mismatches(1));
+        assert_eq!(mismatches, vec![(0, 5), (5, 10), (10, 15)]);
+    }
+
+    #[test]
+    fn approximate_motifs_large_distance() {
+        // which would wrap to an edit distance of 0
+        assert_eq!(
+            find_all_approximate_motifs(CANONICAL, b"GGGGGGGG", Approximate::EditDistance(256)),
+            find_all_approximate_motifs(CANONICAL, b"GGGGGGGG", Approximate::EditDistance(5))
+        );
+        assert!(!find_all_approximate_motifs(
+            CANONICAL,
+            b"GGGGGGGG",
+            Approximate::EditDistance(256)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn approximate_motifs_lowercase() {
+        let lowercase = HAYSTACK.to_lowercase();
+        for approximate in [Approximate::Mismatches(1), Approximate::EditDistance(1)] {
+            assert_eq!(
+                find_approximate_motifs(CANONICAL, lowercase.as_bytes(), approximate),
+                find_approximate_motifs(CANONICAL, HAYSTACK.as_bytes(), approximate)
+            );
+        }
+        assert_eq!(
+            find_approximate_motifs("AWCCT", lowercase.as_bytes(), Approximate::EditDistance(0)),
+            find_approximate_motifs(CANONICAL, HAYSTACK.as_bytes(), Approximate::EditDistance(0))
+        );
+    }
+
+    #[test]
+    fn motif_scanner1() {
+        // the reverse complement of AACCT, and a lowercase haystack
+        let scanner = MotifScanner::new(&["AGGTT".into(), "AACCT".into()]).unwrap();
+        let motifs = scanner.find_all(HAYSTACK.to_lowercase().as_bytes());
+
+        assert_eq!(motifs[0].0.length, 0);
+        assert_eq!(motifs[0].1.indexes, EXPECTED);
+        assert_eq!(motifs[1].0.indexes, EXPECTED);
+        assert_eq!(motifs[1].1.length, 0);
+    }
+
+    #[test]
+    fn bit_scanner1() {
+        let haystack = "AACCTNAACCTAACcTaACCTAAGGTT".repeat(3) + &"AACCT".repeat(8);
+        let motifs = ["AACCT", "AGGTT", "A", &"AACCT".repeat(7)[..32]];
+        let scanner = BitScanner::new(&motifs).unwrap();
+        let found = scanner.find_all(haystack.to_lowercase().as_bytes());
+        for (motif, starts) in motifs.iter().zip(found) {
+            assert!(!starts.is_empty());
+            assert_eq!(
+                starts,
+                find_exact_motifs(motif.as_bytes(), haystack.to_uppercase().as_bytes())
+            );
+        }
+
+        assert!(BitScanner::new(&["AACCN"]).is_err());
+        assert!(BitScanner::new(&["A".repeat(33)]).is_err());
+    }
+
+    #[test]
+    fn motif_scanner_long() {
+        // too long to pack, so found by the automaton
+        let long = "AACCT".repeat(7);
+        let haystack = format!("GG{}GG", long.repeat(2));
+        let scanner = MotifScanner::new(&[long.clone(), "AACCT".into()]).unwrap();
+        let motifs = scanner.find_all(haystack.as_bytes());
+
+        assert_eq!(motifs[0].0.indexes, vec![2, 7, 12, 17, 22, 27, 32, 37]);
+        assert_eq!(motifs[1].0.length, 14);
+    }
+
+    #[test]
+    fn find_motifs_long_lowercase() {
+        // too long for a bit scanner, in a soft masked string
+        let long = "AACCT".repeat(7);
+        let haystack = format!("gg{}GG{}", long.to_lowercase(), long);
+        let expected = vec![2, 39];
+        assert_eq!(find_motifs(&long, &haystack).indexes, expected);
+        assert_eq!(
+            find_motifs(&long.to_lowercase(), &haystack).indexes,
+            expected
+        );
+        // and with BOM, for motifs longer than 64 bases
+        let long = "AACCT".repeat(14);
+        let haystack = format!("gg{}", long.to_lowercase());
+        assert_eq!(
+            find_exact_motifs(long.as_bytes(), haystack.as_bytes()),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn motif_scanner_iupac() {
+        let scanner = MotifScanner::new(&["AACCT".into(), "ARGKT".into()]).unwrap();
+        let motifs = scanner.find_all(HAYSTACK.to_lowercase().as_bytes());
+
+        assert_eq!(motifs[0].0.indexes, EXPECTED);
+        assert_eq!(motifs[1].0.length, 0);
+        assert_eq!(motifs[1].1.indexes, EXPECTED);
+    }
 }